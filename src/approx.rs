@@ -0,0 +1,160 @@
+use std::path;
+use rand::StdRng;
+use mlp::{Mlp, Activation, Init};
+
+/// Function approximator trained on one sample at a time
+pub trait Approx {
+    fn call(&self, x: &[f64]) -> Vec<f64>;
+
+    /// Moves the output for `x` towards `target`
+    fn update(&mut self, target: &[f64], x: &[f64]);
+
+    fn save(&self, filename: &path::Path) -> Result<(), String>;
+
+    /// Fails if the saved approximator has other layers
+    fn load(&mut self, filename: &path::Path) -> Result<(), String>;
+
+    /// Numbers of the inputs, of the hidden units and of the outputs
+    fn sizes(&self) -> Vec<usize>;
+
+    fn print(&self);
+}
+
+/// Network of a learner as written in configuration
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ApproxConfig {
+    /// "mlp", or "fann" when built with the fann feature
+    pub backend: String,
+    /// Sizes of the hidden layers
+    pub hidden: Vec<usize>,
+    /// "tanh", "sigmoid", "relu" or "linear"
+    pub activation: String,
+    pub output_activation: String,
+    /// "uniform" in -init_scale..init_scale, or "xavier"
+    pub init: String,
+    pub init_scale: f64,
+}
+
+impl ApproxConfig {
+    pub fn default() -> ApproxConfig {
+        ApproxConfig {
+            backend: if cfg!(feature = "fann") { "fann" } else { "mlp" }.to_string(),
+            hidden: vec![18, 10],
+            activation: "tanh".to_string(),
+            output_activation: "linear".to_string(),
+            init: "uniform".to_string(),
+            init_scale: 0.1,
+        }
+    }
+}
+
+fn activation(name: &str) -> Result<Activation, String> {
+    Activation::from_name(name).ok_or(format!("Unknown activation: {}", name))
+}
+
+/// Creates the approximator with initial weights drawn from `rng`
+pub fn build(config: &ApproxConfig, inputs: usize, outputs: usize,
+             learning_rate: f64, rng: &mut StdRng) -> Result<Box<Approx>, String> {
+    let hidden = try!(activation(&config.activation));
+    let output = try!(activation(&config.output_activation));
+    let init = match config.init.as_ref() {
+        "uniform" => Init::Uniform(config.init_scale),
+        "xavier" => Init::Xavier,
+        name => return Err(format!("Unknown initialization: {}", name))
+    };
+    let mut sizes = vec![inputs];
+    sizes.extend(config.hidden.iter().cloned());
+    sizes.push(outputs);
+    match config.backend.as_ref() {
+        "mlp" => Ok(Box::new(Mlp::new(&sizes, hidden, output, init, learning_rate, rng))),
+        #[cfg(feature = "fann")]
+        "fann" => fann_approx::FannApprox::new(&sizes, hidden, output, init, learning_rate, rng)
+            .map(|a| Box::new(a) as Box<Approx>),
+        name => Err(format!("Unknown approximator backend: {}", name))
+    }
+}
+
+#[cfg(feature = "fann")]
+mod fann_approx {
+    use fann::{Fann, ActivationFunc, TrainAlgorithm, IncrementalParams};
+    use rand::{Rng, StdRng};
+    use std::path;
+    use libc;
+    use mlp::{Activation, Init};
+    use super::Approx;
+
+    pub struct FannApprox {
+        net: Fann,
+    }
+
+    fn activation_func(a: Activation) -> Result<ActivationFunc, String> {
+        match a {
+            Activation::Linear => Ok(ActivationFunc::Linear),
+            Activation::Tanh => Ok(ActivationFunc::SigmoidSymmetric),
+            Activation::Sigmoid => Ok(ActivationFunc::Sigmoid),
+            Activation::Relu => Err("FANN has no relu activation".to_string()),
+        }
+    }
+
+    impl FannApprox {
+        pub fn new(sizes: &[usize], hidden: Activation, output: Activation, init: Init,
+                   learning_rate: f64, rng: &mut StdRng) -> Result<FannApprox, String> {
+            let scale = match init {
+                Init::Uniform(s) => s,
+                Init::Xavier => return Err("FANN supports only uniform initialization".to_string()),
+            };
+            let layers: Vec<u32> = sizes.iter().map(|&n| n as u32).collect();
+            let mut net = try!(Fann::new(&layers).map_err(|e| format!("{:?}", e)));
+            net.set_activation_func_hidden(try!(activation_func(hidden)));
+            net.set_activation_func_output(try!(activation_func(output)));
+            // FANN draws the initial weights from the C library generator,
+            // seeding it from the clock. Draw them again from the given seed.
+            unsafe {
+                libc::srand(rng.next_u32() as libc::c_uint);
+            }
+            net.randomize_weights(-scale, scale);
+            let train_params = IncrementalParams{learning_momentum: 0.0,
+                                                 learning_rate: learning_rate as f32};
+            net.set_train_algorithm(TrainAlgorithm::Incremental(train_params));
+            Ok(FannApprox {
+                net: net
+            })
+        }
+    }
+
+    impl Approx for FannApprox {
+        fn call(&self, x: &[f64]) -> Vec<f64> {
+            // TODO: optimize!!!
+            self.net.run(x).unwrap()
+        }
+
+        fn update(&mut self, target: &[f64], x: &[f64]) {
+            self.net.train(x, target).unwrap()
+        }
+
+        fn save(&self, filename: &path::Path) -> Result<(), String> {
+            self.net.save(filename).map_err(|e| format!("Cannot save {:?}: {:?}", filename, e))
+        }
+
+        fn load(&mut self, filename: &path::Path) -> Result<(), String> {
+            let net = try!(Fann::from_file(filename)
+                           .map_err(|e| format!("Cannot load {:?}: {:?}", filename, e)));
+            let sizes: Vec<usize> = net.get_layer_array().iter().map(|&n| n as usize).collect();
+            if sizes != self.sizes() {
+                return Err(format!("Network {:?} has layers {:?}, expected {:?}",
+                                   filename, sizes, self.sizes()));
+            }
+            // The activations and the training settings are saved with the net
+            self.net = net;
+            Ok(())
+        }
+
+        fn sizes(&self) -> Vec<usize> {
+            self.net.get_layer_array().iter().map(|&n| n as usize).collect()
+        }
+
+        fn print(&self) {
+            println!("Connections: {:?}", self.net.get_connections());
+        }
+    }
+}
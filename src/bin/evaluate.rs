@@ -0,0 +1,83 @@
+extern crate polygon;
+
+use std::env::args;
+use std::process::exit;
+use std::path::PathBuf;
+use polygon::polygon::{Polygon, workspace_path};
+use polygon::checkpoint::{self, Manifest, read_json, write_json};
+use polygon::eval::EvalOptions;
+use polygon::episode::SpawnConfig;
+
+fn usage() -> ! {
+    println!("Usage: evaluate <workspace> [--episodes N] [--seed N] [--max-steps N] \
+              [--from DIR] [--track FILE] [--spawn X,Y,CX,CY]... [--report FILE] [--record DIR]");
+    exit(1)
+}
+
+fn num(arg: Option<String>) -> u64 {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+// Pose as x,y,course x,course y
+fn pose(arg: Option<String>) -> [f64; 4] {
+    let xs: Vec<f64> = arg.unwrap_or_else(|| usage()).split(',')
+        .map(|x| x.trim().parse().unwrap_or_else(|_| usage()))
+        .collect();
+    if xs.len() != 4 {
+        usage();
+    }
+    [xs[0], xs[1], xs[2], xs[3]]
+}
+
+fn main() {
+    let mut args = args().skip(1);
+    let workspace = args.next().unwrap_or_else(|| usage());
+    let ws_dir = workspace_path(&workspace);
+    let mut opts = EvalOptions::new(10, 0);
+    let mut from = ws_dir.clone();
+    let mut report_file = None;
+    let mut track_file = None;
+    let mut poses = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--episodes" => opts.episodes = num(args.next()) as usize,
+            "--seed" => opts.seed = num(args.next()),
+            "--max-steps" => opts.max_steps = Some(num(args.next()) as u32),
+            "--from" => from = PathBuf::from(args.next().unwrap_or_else(|| usage())),
+            "--track" => track_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--spawn" => poses.push(pose(args.next())),
+            "--record" => opts.record = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--report" => report_file = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => usage()
+        }
+    }
+    let manifest: Manifest = read_json(&from.join(checkpoint::MANIFEST_FILE))
+        .unwrap_or_else(|e| panic!("{}", e));
+    // Evaluation leaves the configuration of the workspace as it is
+    let mut pg = Polygon::with_config(ws_dir, &manifest.config)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(file) = track_file {
+        let mut track = manifest.config.track.clone();
+        track.file = Some(file);
+        opts.track = Some(track);
+    }
+    if !poses.is_empty() {
+        opts.spawn = Some(SpawnConfig {
+            poses: poses,
+            random_along_way: false,
+            max_shift: 0.0,
+            max_angle: 0.0,
+        });
+    }
+    pg.load_checkpoint(&from).unwrap_or_else(|e| panic!("{}", e));
+    let report = pg.evaluate(&opts).unwrap_or_else(|e| panic!("{}", e));
+    for (i, e) in report.episodes.iter().enumerate() {
+        println!("episode {}: return: {:.2}, steps: {}, laps: {:?}, collisions: {}, \
+                  progress: {:.1}, distance: {:.1}, done: {}",
+                 i, e.ret, e.steps, e.lap_times, e.collisions, e.progress, e.distance, e.done);
+    }
+    println!("{}", report.summary());
+    if let Some(filename) = report_file {
+        write_json(&filename, &report).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
@@ -0,0 +1,22 @@
+extern crate polygon;
+
+use std::env::args;
+use std::process::exit;
+
+fn usage() -> ! {
+    println!("Usage: replay <recording> [--resimulate]");
+    exit(1)
+}
+
+fn main() {
+    let mut args = args().skip(1);
+    let filename = args.next().unwrap_or_else(|| usage());
+    let mut resimulate = false;
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--resimulate" => resimulate = true,
+            _ => usage()
+        }
+    }
+    polygon::view::replay(&filename, resimulate);
+}
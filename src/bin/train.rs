@@ -0,0 +1,48 @@
+extern crate polygon;
+
+use std::env::args;
+use std::process::exit;
+use std::time::Duration;
+use std::path::PathBuf;
+use polygon::train::{self, TrainOptions};
+use polygon::metrics::Format;
+
+fn usage() -> ! {
+    println!("Usage: train <workspace> [--cycles N] [--seconds N] \
+              [--checkpoint N] [--keep N] [--eval N] [--eval-steps N] [--report N] [--metrics csv|jsonl|none] [--threads N] [--seed N] \
+              [--config FILE] [--resume | --resume-from DIR]");
+    exit(1)
+}
+
+fn num(arg: Option<String>) -> u64 {
+    arg.and_then(|a| a.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut args = args().skip(1);
+    let workspace = args.next().unwrap_or_else(|| usage());
+    let mut opts = TrainOptions::new();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--cycles" => opts.cycles = Some(num(args.next())),
+            "--seconds" => opts.duration = Some(Duration::from_secs(num(args.next()))),
+            "--checkpoint" => opts.checkpoint_cycles = num(args.next()),
+            "--keep" => opts.keep_checkpoints = num(args.next()) as usize,
+            "--eval" => opts.eval_episodes = num(args.next()) as usize,
+            "--eval-steps" => opts.eval_max_steps = num(args.next()) as u32,
+            "--report" => opts.report_cycles = num(args.next()),
+            "--metrics" => opts.metrics = match args.next() {
+                Some(ref f) if f == "none" => None,
+                Some(f) => Some(Format::from_name(&f).unwrap_or_else(|| usage())),
+                None => usage()
+            },
+            "--threads" => opts.threads = Some(num(args.next()) as usize),
+            "--seed" => opts.seed = Some(num(args.next())),
+            "--config" => opts.config = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--resume" => opts.resume = true,
+            "--resume-from" => opts.resume_from = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => usage()
+        }
+    }
+    train::run(&workspace, &opts);
+}
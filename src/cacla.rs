@@ -1,23 +1,25 @@
 //#![feature(custom_derive, plugin)]
-use fann::{Fann, FannType, ActivationFunc, TrainAlgorithm, IncrementalParams};
 use rand::distributions::{Normal, IndependentSample};
+use rand::StdRng;
 use std::rc::Rc;
-use std::cell::{RefCell, RefMut};
+use std::cell::RefCell;
 use std::ops::DerefMut;
-use rand;
+use seed;
+use approx::{self, Approx, ApproxConfig};
+use learner::Learner;
+use replay::{ReplayBuffer, ReplayConfig, Transition};
 use rustc_serialize::json;
-use std::fs::File;
-use std::io::prelude::*;
 use std::path;
+use checkpoint::{Layers, read_json, write_json};
 
 #[derive(Clone, Debug)]
 pub struct Range {
-    pub lo: FannType,
-    pub hi: FannType,
+    pub lo: f64,
+    pub hi: f64,
 }
 
 impl Range {
-    pub fn new(lo: FannType, hi: FannType) -> Range {
+    pub fn new(lo: f64, hi: f64) -> Range {
         Range {
             lo: lo,
             hi: hi
@@ -32,58 +34,6 @@ impl Range {
     }
 }
 
-struct Approx {
-    net: Fann,
-    ranges: Vec<Range>,
-    hidden: u32,
-    output: u32,
-}
-
-impl Approx {
-    fn new(ranges: &Vec<Range>, hidden: u32, output: u32, learning_rate: f64) -> Approx {
-        //let mut rs = Vec::with_capacity();
-        //rs.clone_from_slice(ranges);
-        let rs = ranges.to_vec();
-        let mut net = Fann::new(&[ranges.len() as u32, hidden, 10, output]).unwrap();
-        //TODO:adjust network params
-        net.set_activation_func_hidden(ActivationFunc::SigmoidSymmetric);
-        net.set_activation_func_output(ActivationFunc::Linear);
-        //net.randomize_weights(-0.001, 0.001);
-        let train_params = IncrementalParams{learning_momentum: 0.0,
-                                             learning_rate: learning_rate as f32};
-        net.set_train_algorithm(TrainAlgorithm::Incremental(train_params));
-        Approx {
-            ranges: rs,
-            hidden: hidden,
-            output: output,
-            net: net,
-        }
-    }
-
-    fn call(&self, x: &[FannType]) -> Vec<FannType> {
-        // TODO: optimize!!!
-        self.net.run(x).unwrap()
-    }
-
-    fn update(&mut self, target: &[FannType], x: &[FannType]) {
-        self.net.train(x, target);
-    }
-
-    fn save(&self, filename: &path::PathBuf) {
-        self.net.save(filename);
-    }
-
-    fn load(&mut self, filename: &path::PathBuf) {
-        // TODO: save and load other settings
-        self.net = Fann::from_file(filename).unwrap()
-    }
-
-    fn print(&self) {
-        //self.net.print_connections();
-        println!("Connections: {:?}", self.net.get_connections());
-    }
-}
-
 #[derive(RustcEncodable, RustcDecodable)]
 pub struct CaclaState {
     action: Vec<f64>,
@@ -95,21 +45,47 @@ pub struct CaclaState {
 }
 
 pub struct Cacla {
-    V: Rc<RefCell<Approx>>,
-    Ac: Rc<RefCell<Approx>>,
+    V: Rc<RefCell<Box<Approx>>>,
+    Ac: Rc<RefCell<Box<Approx>>>,
     pub state: CaclaState,
+    rng: StdRng,
+    /// Past transitions replayed after every step, if enabled
+    replay: Option<Replay>,
+}
+
+struct Replay {
+    buffer: ReplayBuffer,
+    config: ReplayConfig,
+    /// Minibatches due to be replayed
+    credit: f64,
+    rng: StdRng,
 }
 
 impl Cacla {
     pub fn new(state_ranges: &Vec<Range>,
-           dim_actions: u32, hidden: u32,
+           dim_actions: u32, approx: &ApproxConfig,
            gamma: f64,
            alpha: f64,
            beta: f64,
-           sigma: f64) -> Cacla {
+           sigma: f64,
+           replay: Option<&ReplayConfig>,
+           seed: u64) -> Result<Cacla, String> {
         let mut action = Vec::with_capacity(dim_actions as usize);
         action.resize(dim_actions as usize, 0.0);
-        Cacla {
+        let v = try!(approx::build(approx, state_ranges.len(), 1, alpha,
+                                   &mut seed::rng(seed, seed::V_NET)));
+        let ac = try!(approx::build(approx, state_ranges.len(), dim_actions as usize, alpha,
+                                    &mut seed::rng(seed, seed::AC_NET)));
+        let replay = match replay {
+            Some(rc) => Some(Replay {
+                buffer: try!(ReplayBuffer::from_config(rc)),
+                config: rc.clone(),
+                credit: 0.0,
+                rng: seed::rng(seed, seed::REPLAY),
+            }),
+            None => None,
+        };
+        Ok(Cacla {
             state: CaclaState {
                 alpha: alpha,
                 beta: beta,
@@ -118,21 +94,72 @@ impl Cacla {
                 var: 1.0,
                 action: action
             },
-            V: Rc::new(RefCell::new(Approx::new(state_ranges, hidden, 1, alpha))),
-            Ac: Rc::new(RefCell::new(Approx::new(state_ranges, hidden, dim_actions, alpha)))
+            V: Rc::new(RefCell::new(v)),
+            Ac: Rc::new(RefCell::new(ac)),
+            rng: seed::rng(seed, seed::LEARNER),
+            replay: replay,
+        })
+    }
+
+    // Updates the critic and the actor with the transition
+    // and returns its TD error. Only online transitions update the
+    // running variance of the TD error, replayed ones are scaled by it.
+    fn learn(&mut self, old_state: &Vec<f64>, new_state: &Vec<f64>,
+             action: &Vec<f64>, reward: f64, terminal: bool, online: bool) -> f64 {
+        let old_state_v = self.V.borrow().call(old_state);
+        let target = if terminal {
+            &[reward]
+        } else {
+            let new_state_v = self.V.borrow().call(new_state);
+            &[reward + self.state.gamma * new_state_v[0]]
+        };
+        let td_error = target[0] - old_state_v[0];
+        self.V.borrow_mut().update(target, old_state);
+        if td_error > 0.0 {
+            if online {
+                self.state.var = (1.0 - self.state.beta) * self.state.var
+                                + self.state.beta * td_error * td_error;
+            }
+            let n = (td_error / self.state.var.sqrt()).ceil() as usize;
+            for _ in 0..n {
+                self.Ac.borrow_mut().update(action, old_state)
+            }
+        }
+        td_error
+    }
+
+    // Learns again from minibatches of stored transitions.
+    // Prioritized samples are not weighted by importance sampling.
+    fn replay(&mut self) {
+        let mut replay = match self.replay.take() {
+            Some(r) => r,
+            None => return
+        };
+        replay.credit += replay.config.ratio;
+        while replay.credit >= 1.0 {
+            replay.credit -= 1.0;
+            let batch = replay.buffer.sample(replay.config.batch_size, &mut replay.rng);
+            for i in batch {
+                let t = replay.buffer.get(i).clone();
+                let td_error = self.learn(&t.state, &t.next_state, &t.action,
+                                          t.reward, t.terminal, false);
+                replay.buffer.set_td_error(i, td_error);
+            }
         }
+        self.replay = Some(replay);
     }
+}
 
-    pub fn get_action(&mut self, state: &Vec<FannType>, wander_more: bool) -> Vec<f64> {
+impl Learner for Cacla {
+    fn get_action(&mut self, _world: usize, state: &Vec<f64>, _wander_more: bool) -> Vec<f64> {
         let mu = self.Ac.borrow().call(state);
-        let mut rng = rand::thread_rng();
         let mut sigma = self.state.sigma.borrow_mut();
         //if wander_more {
         //    sigma = 1.0
         //}
         for i in 0..mu.len() {
             let normal = Normal::new(mu[i], *sigma.deref_mut());
-            self.state.action[i] = normal.ind_sample(&mut rng);
+            self.state.action[i] = normal.ind_sample(&mut self.rng);
         }
         if *sigma.deref_mut() > 0.1 {
             *sigma.deref_mut() *= 0.99999993068528434627048314517621;
@@ -140,54 +167,65 @@ impl Cacla {
         self.state.action.clone()
     }
 
-    pub fn step(&mut self, old_state: &Vec<FannType>, new_state: &Vec<FannType>,
-            action: &Vec<FannType>, reward: f64) {
-        let old_state_v = self.V.borrow().call(old_state);
-        let new_state_v = self.V.borrow().call(new_state);
-        let target = &[reward + self.state.gamma * new_state_v[0]];
-        let td_error = target[0] - old_state_v[0];
-        self.V.borrow_mut().update(target, old_state);
-        if td_error > 0.0 {
-            self.state.var = (1.0 - self.state.beta) * self.state.var
-                            + self.state.beta * td_error * td_error;
-            let n = (td_error / self.state.var.sqrt()).ceil() as usize;
-            // TODO: print n if n > 5
-            if n > 12 {
-                println!("n = {}", n);
-            }
-            for _ in 0..n {
-                self.Ac.borrow_mut().update(action, old_state)
-            }
+    fn step(&mut self, _world: usize, old_state: &Vec<f64>, new_state: &Vec<f64>,
+            action: &Vec<f64>, reward: f64, terminal: bool) {
+        let td_error = self.learn(old_state, new_state, action, reward, terminal, true);
+        if let Some(ref mut replay) = self.replay {
+            let i = replay.buffer.push(Transition {
+                state: old_state.clone(),
+                action: action.clone(),
+                reward: reward,
+                next_state: new_state.clone(),
+                terminal: terminal
+            });
+            replay.buffer.set_td_error(i, td_error);
         }
+        self.replay();
     }
 
-    pub fn save(&self, dir: &path::PathBuf) {
-        let mut f = File::create(&dir.join("/cacla.state")).unwrap();
-        write!(f, "{}", json::encode(&self.state).unwrap());
-        self.V.borrow().save(&dir.join("/V.net"));
-        self.Ac.borrow().save(&dir.join("/Ac.net"));
+    /// The replay buffer is not saved
+    fn save(&self, dir: &path::Path) -> Result<(), String> {
+        try!(write_json(&dir.join("cacla.state"), &self.state));
+        try!(self.V.borrow().save(&dir.join("V.net")));
+        self.Ac.borrow().save(&dir.join("Ac.net"))
     }
 
-    pub fn load(&mut self, dir: &path::PathBuf) {
-        let mut f = File::open(&dir.join("/cacla.state")).unwrap();
-        let mut js = String::new();
-        f.read_to_string(&mut js);
-        self.state = json::decode(&js).unwrap();
-        self.V.borrow_mut().load(&dir.join("/V.net"));
-        self.Ac.borrow_mut().load(&dir.join("/Ac.net"));
+    fn load(&mut self, dir: &path::Path) -> Result<(), String> {
+        let state: CaclaState = try!(read_json(&dir.join("cacla.state")));
+        if state.action.len() != self.state.action.len() {
+            return Err(format!("Cacla state has {} actions, expected {}",
+                               state.action.len(), self.state.action.len()));
+        }
+        try!(self.V.borrow_mut().load(&dir.join("V.net")));
+        try!(self.Ac.borrow_mut().load(&dir.join("Ac.net")));
+        self.state = state;
+        Ok(())
+    }
+
+    fn networks(&self) -> Vec<Layers> {
+        vec![Layers { name: "V".to_string(), sizes: self.V.borrow().sizes() },
+             Layers { name: "Ac".to_string(), sizes: self.Ac.borrow().sizes() }]
+    }
+
+    fn sigma(&self) -> f64 {
+        *self.state.sigma.borrow()
     }
 
-    pub fn v_fn(&self) -> Box<Fn(&Vec<FannType>) -> Vec<FannType>> {
+    fn v_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
         let V = self.V.clone();
         Box::new(move |x| V.borrow().call(x))
     }
 
-    pub fn ac_fn(&self) -> Box<Fn(&Vec<FannType>) -> Vec<FannType>> {
+    fn metrics(&self) -> Vec<(String, f64)> {
+        vec![("td_var".to_string(), self.state.var)]
+    }
+
+    fn ac_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
         let Ac = self.Ac.clone();
         Box::new(move |x| Ac.borrow().call(x))
     }
 
-    pub fn print(&self) {
+    fn print(&self) {
         println!("Cacla state: {}", json::encode(&self.state).unwrap());
         println!("CONNECTIONS [V]:  ------------------------------");
         self.V.borrow().print();
@@ -195,3 +233,63 @@ impl Cacla {
         self.Ac.borrow().print();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    // Approximator giving the number of its updates
+    struct Counter(Rc<Cell<usize>>);
+
+    impl Approx for Counter {
+        fn call(&self, _x: &[f64]) -> Vec<f64> {
+            vec![self.0.get() as f64]
+        }
+
+        fn update(&mut self, _target: &[f64], _x: &[f64]) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn save(&self, _filename: &path::Path) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn load(&mut self, _filename: &path::Path) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn sizes(&self) -> Vec<usize> {
+            vec![1, 1]
+        }
+
+        fn print(&self) {}
+    }
+
+    #[test]
+    fn steps_replay_minibatches() {
+        let mut replay = ReplayConfig::default();
+        replay.batch_size = 2;
+        replay.ratio = 1.5;
+        replay.prioritized = true;
+        replay.alpha = 1.0;
+        let mut approx = ApproxConfig::default();
+        approx.backend = "mlp".to_string();
+        let mut cacla = Cacla::new(&vec![Range::new(-1.0, 1.0)], 1, &approx,
+                                   0.5, 0.1, 0.5, 0.1, Some(&replay), 1).unwrap();
+        let updates = Rc::new(Cell::new(0));
+        cacla.V = Rc::new(RefCell::new(Box::new(Counter(updates.clone()))));
+        let (state, action) = (vec![0.0], vec![0.0]);
+        // The online TD error is 1, the replayed ones are 0.5 and 0
+        cacla.step(0, &state, &state, &action, 1.0, false);
+        assert_eq!(updates.get(), 1 + 2);
+        assert_eq!(cacla.state.var, 1.0);
+        let p = cacla.replay.as_ref().unwrap().buffer.priority(0).unwrap();
+        assert!((p - 0.01).abs() < 1e-12, "{}", p);
+        // With the half minibatch left over two are due now
+        cacla.step(0, &state, &state, &action, 1.0, false);
+        assert_eq!(updates.get(), 3 + 1 + 2 * 2);
+        cacla.step(0, &state, &state, &action, 1.0, false);
+        assert_eq!(updates.get(), 8 + 1 + 2);
+    }
+}
@@ -1,6 +1,5 @@
-use geom::{self, Pt, Sect, Isx, Figure, Mtx2, recalc_rays};
-use std::rc::Rc;
-use std::cell::RefCell;
+use geom::{self, Pt, Sect, Isx, Figure, Grid, Mtx2};
+use std::sync::Arc;
 use std::f64::consts::PI;
 
 pub struct Car {
@@ -11,22 +10,21 @@ pub struct Car {
     width: f64,
     pub wheels_angle: f64,
     pub speed: f64,
+    pub hit: bool,
     pub rays: Vec<Sect>,
     pub path: Figure,
-    walls: Rc<Figure>,
+    walls: Arc<Grid>,
     pub isxs: Vec<Isx>,
-    self_isxs: Rc<RefCell<Vec<Isx>>>,
+    self_isxs: Arc<Vec<Isx>>,
 }
 
 impl Car {
     pub fn new(center: Pt, course: Pt, length: f64, width: f64,
-                nrays: usize, walls: Rc<Figure>) -> Car {
+                nrays: usize, walls: Arc<Grid>) -> Car {
         let mut rays = Vec::with_capacity(nrays);
         rays.resize(nrays, Sect::zero());
         let mut isxs = Vec::with_capacity(nrays);
         isxs.resize(nrays, Isx::zero());
-        let mut self_isxs = Vec::with_capacity(nrays);
-        self_isxs.resize(nrays, Isx::zero());
         let path = Figure::void();
         let mut car = Car {
             center: center,
@@ -36,15 +34,17 @@ impl Car {
             base: length,
             wheels_angle: 0.0,
             speed: 0.0,
+            hit: false,
             rays: rays,
             path: path,
             walls: walls,
             isxs: isxs,
-            self_isxs: Rc::new(RefCell::new(self_isxs))
+            self_isxs: Arc::new(Vec::new())
         };
         car.recalc_rays();
         car.recalc_path();
         car.calc_self_isxs();
+        car.cast_rays();
         car
     }
 
@@ -57,6 +57,7 @@ impl Car {
             base: self.base,
             wheels_angle: self.wheels_angle,
             speed: self.speed,
+            hit: self.hit,
             rays: self.rays.clone(),
             path: self.path.clone(),
             walls: self.walls.clone(),
@@ -72,6 +73,15 @@ impl Car {
         self.recalc_path();
     }
 
+    /// Puts the stopped car to the given position
+    pub fn reset(&mut self, center: Pt, course: Pt) {
+        self.speed = 0.0;
+        self.wheels_angle = 0.0;
+        self.hit = false;
+        self.set_pos(center, course);
+        self.cast_rays();
+    }
+
     pub fn action_penalty(&self, action: &[f64]) -> f64 {
         let h = 0.1f64;
         let m = 8i32;
@@ -107,12 +117,16 @@ impl Car {
         self.move_or_stop(0.1);
     }
 
+    // Distances from the center to the car's own borders along the rays.
+    // They do not depend on the position, so are calculated once and shared.
     fn calc_self_isxs(&mut self) {
+        let mut self_isxs = vec![Isx::zero(); self.rays.len()];
         geom::rays_figure_intersections(&self.rays, &self.path,
-                                       -1.0, self.self_isxs.borrow_mut().as_mut());
+                                       -1.0, self_isxs.as_mut());
+        self.self_isxs = Arc::new(self_isxs);
         //println!("self isxs: {:?}", self.self_isxs);
         /*
-        for i in self.self_isxs.borrow().as_ref() {
+        for i in self.self_isxs.iter() {
             println!("{}", i.dist);
         }
         */
@@ -138,10 +152,9 @@ impl Car {
         self.mv(dt);
         self.recalc_path();
         //println!("path={:?}", self.path);
-        let intscts = geom::figures_intersect(
-            &self.path,
-            &self.walls);
+        let intscts = self.walls.intersects_figure(&self.path);
         //println!("intscts={}", intscts);
+        self.hit = intscts;
         if intscts {
             self.center = center;
             self.course = course;
@@ -152,12 +165,16 @@ impl Car {
             self.recalc_path(); // !!!???
         } else {
             self.recalc_rays();
-            geom::rays_figure_intersections(&self.rays, &self.walls,
-                                           -1.0, self.isxs.as_mut());
-            for i in 0..self.isxs.len() {
-                if self.isxs[i].dist >= 0.0 {
-                    self.isxs[i].dist -= self.self_isxs.borrow()[i].dist;
-                }
+            self.cast_rays();
+        }
+    }
+
+    fn cast_rays(&mut self) {
+        geom::rays_grid_intersections(&self.rays, &self.walls,
+                                      -1.0, self.isxs.as_mut());
+        for i in 0..self.isxs.len() {
+            if self.isxs[i].dist >= 0.0 {
+                self.isxs[i].dist -= self.self_isxs[i].dist;
             }
         }
     }
@@ -172,12 +189,11 @@ impl Car {
 
     fn move_with_turn(&mut self, dt: f64) {
         let beta = -self.speed * dt * self.wheels_angle.tan() / self.base;
-        let mut pg = Pt::zero();
-        if self.wheels_angle > 0.0 {
-            pg = rperp(self.course);
+        let pg = if self.wheels_angle > 0.0 {
+            rperp(self.course)
         } else {
-            pg = lperp(self.course);
-        }
+            lperp(self.course)
+        };
         let rot_center = self.center - 0.5*self.base * self.course
             + self.base / self.wheels_angle.tan().abs() * pg;
         let s = beta.sin();
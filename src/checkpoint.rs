@@ -0,0 +1,176 @@
+//! Checkpoint of an experiment: a directory with the manifest and
+//! the files of the learner. The manifest records what the learner
+//! was trained on, so it is not loaded into a different setup.
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path;
+use rustc_serialize::{json, Encodable, Decodable};
+use config::Config;
+
+/// Version of the checkpoint format written by this build
+pub const VERSION: u32 = 1;
+
+pub const MANIFEST_FILE: &'static str = "checkpoint.json";
+pub const PROGRESS_FILE: &'static str = "progress.json";
+/// Directory of the periodic checkpoints in the workspace
+pub const CHECKPOINTS_DIR: &'static str = "checkpoints";
+/// Directory of the checkpoint with the best evaluation return
+pub const BEST_DIR: &'static str = "best";
+
+/// Sizes of the layers of a network of the learner
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Layers {
+    pub name: String,
+    pub sizes: Vec<usize>,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Manifest {
+    pub version: u32,
+    pub state_dim: usize,
+    pub action_dim: usize,
+    /// Normalization ranges of the state
+    pub ranges: Vec<[f64; 2]>,
+    pub networks: Vec<Layers>,
+    pub config: Config,
+}
+
+impl Manifest {
+    /// Error unless a learner with these dimensions, ranges
+    /// and networks can load the checkpoint
+    pub fn check(&self, expected: &Manifest) -> Result<(), String> {
+        if self.version != VERSION {
+            return Err(format!("Checkpoint version {} is not supported (expected {})",
+                               self.version, VERSION));
+        }
+        if self.config.learner.algorithm != expected.config.learner.algorithm {
+            return Err(format!("Checkpoint of learner {}, expected {}",
+                               self.config.learner.algorithm,
+                               expected.config.learner.algorithm));
+        }
+        if self.state_dim != expected.state_dim || self.action_dim != expected.action_dim {
+            return Err(format!("Checkpoint has state dimension {} and action dimension {}, \
+                                expected {} and {}",
+                               self.state_dim, self.action_dim,
+                               expected.state_dim, expected.action_dim));
+        }
+        if self.ranges != expected.ranges {
+            return Err("Checkpoint has different normalization ranges".to_string());
+        }
+        if self.networks != expected.networks {
+            return Err(format!("Checkpoint has networks {:?}, expected {:?}",
+                               self.networks, expected.networks));
+        }
+        Ok(())
+    }
+}
+
+/// Where the training is, saved with the learner
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Progress {
+    pub cycles: u64,
+    pub episodes: u64,
+    /// Average reward of the first world by cycles
+    pub reward_history: Vec<(u64, f64)>,
+    /// Best evaluation return so far
+    pub best_return: Option<f64>,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            cycles: 0,
+            episodes: 0,
+            reward_history: Vec::new(),
+            best_return: None
+        }
+    }
+}
+
+/// Periodic checkpoints in subdirectories named by their cycles,
+/// of which the last `keep` are kept (all if `keep` is 0)
+pub struct Checkpoints {
+    dir: path::PathBuf,
+    keep: usize,
+}
+
+impl Checkpoints {
+    pub fn new(ws_dir: &path::Path, keep: usize) -> Checkpoints {
+        Checkpoints {
+            dir: ws_dir.join(CHECKPOINTS_DIR),
+            keep: keep
+        }
+    }
+
+    pub fn path(&self, cycles: u64) -> path::PathBuf {
+        self.dir.join(format!("cycle-{:012}", cycles))
+    }
+
+    /// Complete checkpoints, the oldest first
+    pub fn list(&self) -> Vec<path::PathBuf> {
+        let mut dirs: Vec<path::PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.join(MANIFEST_FILE).exists())
+                .collect(),
+            Err(_) => Vec::new()
+        };
+        dirs.sort();
+        dirs
+    }
+
+    pub fn latest(&self) -> Option<path::PathBuf> {
+        self.list().pop()
+    }
+
+    /// Removes all but the last `keep` checkpoints
+    pub fn rotate(&self) -> Result<(), String> {
+        let dirs = self.list();
+        if self.keep == 0 || dirs.len() <= self.keep {
+            return Ok(());
+        }
+        for dir in dirs[..dirs.len() - self.keep].iter() {
+            try!(fs::remove_dir_all(dir).map_err(|e| format!("Cannot remove {:?}: {}", dir, e)));
+        }
+        Ok(())
+    }
+}
+
+pub fn write_json<T: Encodable>(filename: &path::Path, value: &T) -> Result<(), String> {
+    File::create(filename)
+        .and_then(|mut f| write!(f, "{}", json::as_pretty_json(value)))
+        .map_err(|e| format!("Cannot write {:?}: {}", filename, e))
+}
+
+pub fn read_json<T: Decodable>(filename: &path::Path) -> Result<T, String> {
+    let mut js = String::new();
+    try!(File::open(filename)
+         .and_then(|mut f| f.read_to_string(&mut js))
+         .map_err(|e| format!("Cannot read {:?}: {}", filename, e)));
+    json::decode(&js).map_err(|e| format!("Bad file {:?}: {}", filename, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+
+    #[test]
+    fn rotation_keeps_the_latest_complete_checkpoints() {
+        let ws_dir = env::temp_dir().join("polygon-rotation-test");
+        let _ = fs::remove_dir_all(&ws_dir);
+        let checkpoints = Checkpoints::new(&ws_dir, 2);
+        for &cycles in [100, 200, 300].iter() {
+            let dir = checkpoints.path(cycles);
+            fs::create_dir_all(&dir).unwrap();
+            File::create(dir.join(MANIFEST_FILE)).unwrap();
+        }
+        // Interrupted before the manifest was written
+        fs::create_dir_all(checkpoints.path(400)).unwrap();
+        checkpoints.rotate().unwrap();
+        assert_eq!(checkpoints.list(), vec![checkpoints.path(200), checkpoints.path(300)]);
+        assert_eq!(checkpoints.latest(), Some(checkpoints.path(300)));
+        fs::remove_dir_all(&ws_dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path;
+use rustc_serialize::json;
+use reward::RewardConfig;
+use observation::ObservationConfig;
+use episode::EpisodeConfig;
+use approx::ApproxConfig;
+use ddpg::DdpgConfig;
+use ppo::PpoConfig;
+use replay::ReplayConfig;
+use trackgen::TrackGenConfig;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TrackConfig {
+    /// "clover", or "generated" for a random track
+    pub name: String,
+    pub scale: f64,
+    pub half_width: f64,
+    /// Track file to use instead of the built in track. Its scale
+    /// and half width replace those above, its spawn poses replace
+    /// those of the episode configuration.
+    pub file: Option<String>,
+    /// Settings of "generated"; defaults if not given
+    pub generator: Option<TrackGenConfig>,
+    /// Cell size of the grid used to find walls near cars and rays
+    pub grid_cell: f64,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct CarConfig {
+    pub length: f64,
+    pub width: f64,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct SensorsConfig {
+    pub nrays: usize,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct LearnerConfig {
+    /// "cacla", "ddpg", "td3" or "ppo"
+    pub algorithm: String,
+    /// Networks of both the critic and the actor
+    pub approx: ApproxConfig,
+    pub gamma: f64,
+    pub alpha: f64,
+    pub beta: f64,
+    pub sigma: f64,
+    /// Settings of "ddpg" and "td3"; defaults if not given
+    pub ddpg: Option<DdpgConfig>,
+    /// Settings of "ppo"; defaults if not given
+    pub ppo: Option<PpoConfig>,
+    /// Experience replay of "cacla"; none if not given
+    pub replay: Option<ReplayConfig>,
+}
+
+/// Everything that defines an experiment. Normalization ranges
+/// come with the observation features and the reward.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Config {
+    pub seed: u64,
+    pub worlds: usize,
+    /// Threads stepping the worlds; more than one changes
+    /// the order of learner updates (see `Polygon::set_threads`)
+    pub threads: usize,
+    pub track: TrackConfig,
+    pub car: CarConfig,
+    pub sensors: SensorsConfig,
+    pub learner: LearnerConfig,
+    pub reward: RewardConfig,
+    pub observation: ObservationConfig,
+    pub episode: EpisodeConfig,
+}
+
+pub const CONFIG_FILE: &'static str = "config.json";
+
+impl Config {
+    pub fn default() -> Config {
+        Config {
+            seed: 0,
+            worlds: 20,
+            threads: 1,
+            track: TrackConfig {
+                name: "clover".to_string(),
+                scale: 10.0,
+                half_width: 4.0,
+                file: None,
+                generator: None,
+                grid_cell: 5.0,
+            },
+            car: CarConfig {
+                length: 3.0,
+                width: 1.6,
+            },
+            sensors: SensorsConfig {
+                nrays: 36,
+            },
+            learner: LearnerConfig {
+                algorithm: "cacla".to_string(),
+                approx: ApproxConfig::default(),
+                gamma: 0.99,
+                alpha: 0.1,
+                beta: 0.001,
+                sigma: 0.1,
+                ddpg: None,
+                ppo: None,
+                replay: None,
+            },
+            reward: RewardConfig::preset("default"),
+            observation: ObservationConfig::rays(),
+            episode: EpisodeConfig::default(),
+        }
+    }
+
+    pub fn load(filename: &path::Path) -> Result<Config, String> {
+        let mut js = String::new();
+        try!(File::open(filename)
+             .and_then(|mut f| f.read_to_string(&mut js))
+             .map_err(|e| format!("Cannot read {:?}: {}", filename, e)));
+        json::decode(&js).map_err(|e| format!("Bad config {:?}: {}", filename, e))
+    }
+
+    pub fn save(&self, filename: &path::Path) -> io::Result<()> {
+        let mut f = try!(File::create(filename));
+        write!(f, "{}", json::as_pretty_json(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustc_serialize::json;
+
+    #[test]
+    fn default_config_round_trips() {
+        let config = Config::default();
+        let js = json::as_pretty_json(&config).to_string();
+        let decoded: Config = json::decode(&js).unwrap();
+        assert_eq!(js, json::as_pretty_json(&decoded).to_string());
+    }
+
+    #[test]
+    fn config_without_laps_decodes() {
+        let mut config = Config::default();
+        config.episode.laps = Some(::laps::LapConfig::default());
+        let mut js = json::Json::from_str(&json::encode(&config).unwrap()).unwrap();
+        js.as_object_mut().unwrap().get_mut("episode").unwrap()
+            .as_object_mut().unwrap().remove("laps");
+        let decoded: Config = json::decode(&js.to_string()).unwrap();
+        assert!(decoded.episode.laps.is_none());
+    }
+}
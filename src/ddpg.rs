@@ -0,0 +1,341 @@
+//! Deep deterministic policy gradient, and its twin delayed
+//! variant TD3: twin critics, delayed actor updates and
+//! smoothing of the target policy.
+use rand::distributions::{Normal, IndependentSample};
+use rand::StdRng;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path;
+use rustc_serialize::json;
+use checkpoint::{Layers, read_json, write_json};
+use seed;
+use approx::{Approx, ApproxConfig};
+use mlp::{Mlp, Activation, Init};
+use replay::{ReplayBuffer, Transition};
+use learner::Learner;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct DdpgConfig {
+    pub buffer_size: usize,
+    pub batch_size: usize,
+    /// Transitions collected before learning starts
+    pub warmup: usize,
+    /// Learn from a batch every that many steps
+    pub train_every: u32,
+    pub actor_lr: f64,
+    pub critic_lr: f64,
+    /// Rate of the soft updates of the target networks
+    pub tau: f64,
+    /// TD3: the actor and the targets are updated
+    /// once per that many critic updates
+    pub policy_delay: u32,
+    /// TD3: noise added to the target actions, and its limit
+    pub target_noise: f64,
+    pub noise_clip: f64,
+}
+
+impl DdpgConfig {
+    pub fn default() -> DdpgConfig {
+        DdpgConfig {
+            buffer_size: 100000,
+            batch_size: 32,
+            warmup: 1000,
+            train_every: 1,
+            actor_lr: 0.01,
+            critic_lr: 0.1,
+            tau: 0.005,
+            policy_delay: 2,
+            target_noise: 0.2,
+            noise_clip: 0.5,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct DdpgState {
+    gamma: f64,
+    sigma: f64,
+    steps: u64,
+    updates: u64,
+}
+
+/// The networks always are pure-Rust perceptrons, whatever the
+/// backend in the approximator config: learning needs the gradient
+/// of the critic by the action. The actor ends with tanh.
+pub struct Ddpg {
+    actor: Rc<RefCell<Mlp>>,
+    /// One critic for DDPG, two for TD3
+    critics: Rc<RefCell<Vec<Mlp>>>,
+    actor_target: Mlp,
+    critic_targets: Vec<Mlp>,
+    buffer: ReplayBuffer,
+    config: DdpgConfig,
+    td3: bool,
+    action_dim: usize,
+    pub state: DdpgState,
+    rng: StdRng,
+}
+
+fn clamp(x: f64, lo: f64, hi: f64) -> f64 {
+    if x < lo {
+        lo
+    } else if x > hi {
+        hi
+    } else {
+        x
+    }
+}
+
+fn state_action(s: &[f64], a: &[f64]) -> Vec<f64> {
+    let mut x = s.to_vec();
+    x.extend_from_slice(a);
+    x
+}
+
+impl Ddpg {
+    pub fn new(state_dim: usize, action_dim: usize,
+               approx: &ApproxConfig, config: &DdpgConfig,
+               gamma: f64, sigma: f64, td3: bool, seed: u64) -> Result<Ddpg, String> {
+        let hidden = try!(Activation::from_name(&approx.activation)
+                          .ok_or(format!("Unknown activation: {}", approx.activation)));
+        let init = match approx.init.as_ref() {
+            "uniform" => Init::Uniform(approx.init_scale),
+            "xavier" => Init::Xavier,
+            name => return Err(format!("Unknown initialization: {}", name))
+        };
+        if config.buffer_size == 0 {
+            return Err("Replay buffer size must be positive".to_string());
+        }
+        let sizes = |inputs, outputs| {
+            let mut sizes = vec![inputs];
+            sizes.extend(approx.hidden.iter().cloned());
+            sizes.push(outputs);
+            sizes
+        };
+        let actor = Mlp::new(&sizes(state_dim, action_dim), hidden, Activation::Tanh, init,
+                             config.actor_lr, &mut seed::rng(seed, seed::AC_NET));
+        let mut critic_rng = seed::rng(seed, seed::V_NET);
+        let critics: Vec<Mlp> = (0..if td3 { 2 } else { 1 }).map(|_| {
+            Mlp::new(&sizes(state_dim + action_dim, 1), hidden, Activation::Linear, init,
+                     config.critic_lr, &mut critic_rng)
+        }).collect();
+        Ok(Ddpg {
+            actor_target: actor.clone(),
+            critic_targets: critics.clone(),
+            actor: Rc::new(RefCell::new(actor)),
+            critics: Rc::new(RefCell::new(critics)),
+            buffer: ReplayBuffer::new(config.buffer_size),
+            config: config.clone(),
+            td3: td3,
+            action_dim: action_dim,
+            state: DdpgState {
+                gamma: gamma,
+                sigma: sigma,
+                steps: 0,
+                updates: 0
+            },
+            rng: seed::rng(seed, seed::LEARNER),
+        })
+    }
+
+    fn target_action(&mut self, s: &[f64]) -> Vec<f64> {
+        let mut a = self.actor_target.call(s);
+        if self.td3 && self.config.target_noise > 0.0 {
+            let normal = Normal::new(0.0, self.config.target_noise);
+            let c = self.config.noise_clip;
+            for x in a.iter_mut() {
+                *x = clamp(*x + clamp(normal.ind_sample(&mut self.rng), -c, c), -1.0, 1.0);
+            }
+        }
+        a
+    }
+
+    fn train(&mut self) {
+        let batch = self.buffer.sample(self.config.batch_size, &mut self.rng);
+        let mut targets = Vec::with_capacity(batch.len());
+        for &i in batch.iter() {
+            let t = self.buffer.get(i).clone();
+            let y = if t.terminal {
+                t.reward
+            } else {
+                let a = self.target_action(&t.next_state);
+                let x = state_action(&t.next_state, &a);
+                let q = self.critic_targets.iter()
+                    .map(|c| c.call(&x)[0])
+                    .fold(::std::f64::INFINITY, f64::min);
+                t.reward + self.state.gamma * q
+            };
+            targets.push(y);
+        }
+        // Sample by sample, each with its share of the learning
+        // rate, which is close to a step along the batch gradient
+        let n = batch.len() as f64;
+        {
+            let mut critics = self.critics.borrow_mut();
+            for (&i, y) in batch.iter().zip(targets.iter()) {
+                let t = self.buffer.get(i);
+                let x = state_action(&t.state, &t.action);
+                for c in critics.iter_mut() {
+                    let q = c.call(&x)[0];
+                    c.ascend(&x, &[y - q], self.config.critic_lr / n);
+                }
+            }
+        }
+        self.state.updates += 1;
+        if self.td3 && self.state.updates % (self.config.policy_delay.max(1) as u64) != 0 {
+            return;
+        }
+        {
+            let critics = self.critics.borrow();
+            let mut actor = self.actor.borrow_mut();
+            for &i in batch.iter() {
+                let s = &self.buffer.get(i).state;
+                let a = actor.call(s);
+                let g = critics[0].input_gradient(&state_action(s, &a), &[1.0]);
+                actor.ascend(s, &g[s.len()..], self.config.actor_lr / n);
+            }
+        }
+        let tau = self.config.tau;
+        self.actor_target.soft_update(&self.actor.borrow(), tau);
+        for (t, c) in self.critic_targets.iter_mut().zip(self.critics.borrow().iter()) {
+            t.soft_update(c, tau);
+        }
+    }
+
+    fn critic_names(&self) -> Vec<String> {
+        (0..self.critic_targets.len()).map(|i| format!("critic{}", i + 1)).collect()
+    }
+}
+
+impl Learner for Ddpg {
+    fn get_action(&mut self, _world: usize, state: &Vec<f64>, _wander_more: bool) -> Vec<f64> {
+        let mut a = self.actor.borrow().call(state);
+        let normal = Normal::new(0.0, self.state.sigma);
+        for x in a.iter_mut() {
+            *x = clamp(*x + normal.ind_sample(&mut self.rng), -1.0, 1.0);
+        }
+        a
+    }
+
+    fn step(&mut self, _world: usize, old_state: &Vec<f64>, new_state: &Vec<f64>,
+            action: &Vec<f64>, reward: f64, terminal: bool) {
+        self.buffer.push(Transition {
+            state: old_state.clone(),
+            action: action.clone(),
+            reward: reward,
+            next_state: new_state.clone(),
+            terminal: terminal
+        });
+        self.state.steps += 1;
+        if self.buffer.len() >= self.config.warmup
+            && self.state.steps % (self.config.train_every.max(1) as u64) == 0 {
+            self.train();
+        }
+    }
+
+    /// The replay buffer is not saved
+    fn save(&self, dir: &path::Path) -> Result<(), String> {
+        try!(write_json(&dir.join("ddpg.state"), &self.state));
+        let names = self.critic_names();
+        try!(self.actor.borrow().save(&dir.join("actor.net")));
+        try!(self.actor_target.save(&dir.join("actor_target.net")));
+        let critics = self.critics.borrow();
+        for (i, name) in names.iter().enumerate() {
+            try!(critics[i].save(&dir.join(format!("{}.net", name))));
+            try!(self.critic_targets[i].save(&dir.join(format!("{}_target.net", name))));
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, dir: &path::Path) -> Result<(), String> {
+        let state: DdpgState = try!(read_json(&dir.join("ddpg.state")));
+        let names = self.critic_names();
+        try!(self.actor.borrow_mut().load(&dir.join("actor.net")));
+        try!(self.actor_target.load(&dir.join("actor_target.net")));
+        let mut critics = self.critics.borrow_mut();
+        for (i, name) in names.iter().enumerate() {
+            try!(critics[i].load(&dir.join(format!("{}.net", name))));
+            try!(self.critic_targets[i].load(&dir.join(format!("{}_target.net", name))));
+        }
+        self.state = state;
+        Ok(())
+    }
+
+    fn networks(&self) -> Vec<Layers> {
+        let mut networks = vec![Layers { name: "actor".to_string(),
+                                         sizes: self.actor.borrow().sizes() }];
+        for (name, c) in self.critic_names().into_iter().zip(self.critics.borrow().iter()) {
+            networks.push(Layers { name: name, sizes: c.sizes() });
+        }
+        networks
+    }
+
+    fn sigma(&self) -> f64 {
+        self.state.sigma
+    }
+
+    /// Q of the state and the action of the actor
+    fn v_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
+        let actor = self.actor.clone();
+        let critics = self.critics.clone();
+        Box::new(move |x| {
+            let a = actor.borrow().call(x);
+            critics.borrow()[0].call(&state_action(x, &a))
+        })
+    }
+
+    fn ac_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
+        let actor = self.actor.clone();
+        Box::new(move |x| actor.borrow().call(x))
+    }
+
+    fn print(&self) {
+        println!("{} state: {}", if self.td3 { "TD3" } else { "DDPG" },
+                 json::encode(&self.state).unwrap());
+        println!("ACTOR: ------------------------------");
+        self.actor.borrow().print();
+        for (i, c) in self.critics.borrow().iter().enumerate() {
+            println!("CRITIC {}: ------------------------------", i + 1);
+            c.print();
+        }
+        println!("Actions: {}, replay buffer: {}", self.action_dim, self.buffer.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::ApproxConfig;
+    use learner::Learner;
+
+    // One-step episodes rewarding the action 0.5
+    fn bandit(td3: bool) -> f64 {
+        let mut config = DdpgConfig::default();
+        config.warmup = 100;
+        config.actor_lr = 0.01;
+        config.critic_lr = 0.1;
+        let mut approx = ApproxConfig::default();
+        approx.hidden = vec![16];
+        approx.init = "xavier".to_string();
+        let mut ddpg = Ddpg::new(1, 1, &approx, &config, 0.9, 0.3, td3, 7).unwrap();
+        let s = vec![0.0];
+        for _ in 0..5000 {
+            let a = ddpg.get_action(0, &s, false);
+            let r = -(a[0] - 0.5) * (a[0] - 0.5);
+            ddpg.step(0, &s, &s, &a, r, true);
+        }
+        ddpg.ac_fn()(&s)[0]
+    }
+
+    #[test]
+    fn ddpg_finds_the_best_action() {
+        let a = bandit(false);
+        assert!((a - 0.5).abs() < 0.1, "{}", a);
+    }
+
+    #[test]
+    fn td3_finds_the_best_action() {
+        let a = bandit(true);
+        assert!((a - 0.5).abs() < 0.1, "{}", a);
+    }
+}
@@ -0,0 +1,70 @@
+use cacla::Range;
+use polygon::{World, action_ranges};
+use seed;
+
+pub type Observation = Vec<f64>;
+
+/// Why an episode has finished
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Done {
+    Collision,
+    Timeout,
+    LapFinished,
+    Stopped,
+    Backwards,
+}
+
+/// Extra information about a step
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub done: Option<Done>,
+    pub steps: u32,
+    pub progress: f64,
+}
+
+/// Episodic environment in the spirit of OpenAI Gym.
+///
+/// Observations are not normalized: `observation_space` tells
+/// the ranges the learner should normalize them from.
+pub trait Environment {
+    /// Starts a new episode. The seed reseeds the random sources
+    /// of the environment, so that episodes can be reproduced; a world
+    /// gets the stream of world 0 of an experiment with the same seed.
+    fn reset(&mut self, seed: u64) -> Observation;
+
+    /// Applies the action and returns the new observation, the reward,
+    /// whether the episode is over, and the details of the step.
+    fn step(&mut self, action: &[f64]) -> (Observation, f64, bool, Info);
+
+    fn observation_space(&self) -> Vec<Range>;
+
+    fn action_space(&self) -> Vec<Range>;
+}
+
+impl Environment for World {
+    fn reset(&mut self, seed: u64) -> Observation {
+        self.rng = seed::rng(seed, seed::WORLDS);
+        World::reset(self);
+        self.state.clone()
+    }
+
+    fn step(&mut self, action: &[f64]) -> (Observation, f64, bool, Info) {
+        self.act(action);
+        let reward = self.reward();
+        let done = self.done();
+        let info = Info {
+            done: done,
+            steps: self.episode.steps,
+            progress: self.episode.progress,
+        };
+        (self.state.clone(), reward, done.is_some(), info)
+    }
+
+    fn observation_space(&self) -> Vec<Range> {
+        self.observer.ranges()
+    }
+
+    fn action_space(&self) -> Vec<Range> {
+        action_ranges()
+    }
+}
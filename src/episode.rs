@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use geom::Pt;
+use track::Way;
+use env::Done;
+use laps::{LapConfig, LapTracker};
+use rand::{Rng, StdRng};
+
+/// When an episode of a world is over. Conditions set to `None`
+/// (or `false`) are not checked.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TerminationConfig {
+    pub collision: bool,
+    pub max_stopped_steps: Option<u32>,
+    pub max_steps: Option<u32>,
+    /// A lap through all the checkpoints
+    pub lap: bool,
+    /// Distance the car may drive back from its best progress along the way
+    pub max_backwards: Option<f64>,
+}
+
+/// Where a car starts its episodes: either one of the fixed poses
+/// (x, y, course x, course y), or a random point of the way looking
+/// along it. Both are perturbed by up to `max_shift` sideways
+/// and by up to `max_angle` radians in course.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct SpawnConfig {
+    pub poses: Vec<[f64; 4]>,
+    pub random_along_way: bool,
+    pub max_shift: f64,
+    pub max_angle: f64,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct EpisodeConfig {
+    pub termination: TerminationConfig,
+    pub spawn: SpawnConfig,
+    /// Checkpoints of the laps; defaults if not given
+    pub laps: Option<LapConfig>,
+}
+
+impl EpisodeConfig {
+    /// Episodes end on a collision, after 50 stopped steps, after 5000
+    /// steps or 50 units behind the best progress. Configurations written
+    /// before episodes existed ran forever; set all the termination
+    /// conditions to `null` (and `collision` to `false`) to keep that.
+    pub fn default() -> EpisodeConfig {
+        EpisodeConfig {
+            termination: TerminationConfig {
+                collision: true,
+                max_stopped_steps: Some(50),
+                max_steps: Some(5000),
+                lap: false,
+                max_backwards: Some(50.0),
+            },
+            spawn: SpawnConfig {
+                poses: vec![[-110.0, 0.0, 0.0, 1.0]],
+                random_along_way: false,
+                max_shift: 0.0,
+                max_angle: 0.0,
+            },
+            laps: None,
+        }
+    }
+
+    /// Picks the spawn pose (center, course) for a new episode
+    pub fn spawn(&self, way: &Way, rng: &mut StdRng) -> (Pt, Pt) {
+        let sc = &self.spawn;
+        let (center, course) = if sc.random_along_way || sc.poses.is_empty() {
+            let wp = way.at_distance(rng.gen_range(0.0, way.length()));
+            (way.point(&wp), way.direction(&wp))
+        } else {
+            let p = sc.poses[rng.gen_range(0, sc.poses.len())];
+            let course = Pt::new(p[2], p[3]);
+            (Pt::new(p[0], p[1]), 1.0 / course.norm() * course)
+        };
+        let shift = if sc.max_shift > 0.0 { rng.gen_range(-sc.max_shift, sc.max_shift) } else { 0.0 };
+        let angle = if sc.max_angle > 0.0 { rng.gen_range(-sc.max_angle, sc.max_angle) } else { 0.0 };
+        let normal = Pt::new(-course.y, course.x);
+        let (s, c) = angle.sin_cos();
+        (center + shift * normal,
+         Pt::new(c * course.x - s * course.y, s * course.x + c * course.y))
+    }
+
+    /// Tracker of the laps of an episode starting at `center`
+    pub fn lap_tracker(&self, way: &Way, center: Pt) -> LapTracker {
+        let config = self.laps.clone().unwrap_or_else(LapConfig::default);
+        LapTracker::new(&config, way, center)
+    }
+
+    /// Checks the termination conditions for the episode state
+    pub fn done(&self, ep: &EpisodeState, laps: &LapTracker, hit: bool) -> Option<Done> {
+        let tc = &self.termination;
+        if tc.collision && hit {
+            return Some(Done::Collision);
+        }
+        if tc.lap && laps.laps > 0 {
+            return Some(Done::LapFinished);
+        }
+        if let Some(d) = tc.max_backwards {
+            if ep.best_progress - ep.progress > d {
+                return Some(Done::Backwards);
+            }
+        }
+        if let Some(n) = tc.max_stopped_steps {
+            if ep.stopped_steps >= n {
+                return Some(Done::Stopped);
+            }
+        }
+        if let Some(n) = tc.max_steps {
+            if ep.steps >= n {
+                return Some(Done::Timeout);
+            }
+        }
+        None
+    }
+}
+
+/// Counters of the current episode of a world
+#[derive(Clone, Copy, Debug)]
+pub struct EpisodeState {
+    pub steps: u32,
+    pub stopped_steps: u32,
+    pub progress: f64,
+    pub best_progress: f64,
+    pub ret: f64,
+    /// Times the car ran into a wall
+    pub collisions: u32,
+}
+
+impl EpisodeState {
+    pub fn new() -> EpisodeState {
+        EpisodeState {
+            steps: 0,
+            stopped_steps: 0,
+            progress: 0.0,
+            best_progress: 0.0,
+            ret: 0.0,
+            collisions: 0
+        }
+    }
+
+    pub fn update(&mut self, reward: f64, offset: f64, speed: f64) {
+        self.steps += 1;
+        self.ret += reward;
+        self.progress += offset;
+        if self.progress > self.best_progress {
+            self.best_progress = self.progress;
+        }
+        if speed.abs() < 0.001 {
+            self.stopped_steps += 1;
+        } else {
+            self.stopped_steps = 0;
+        }
+    }
+}
+
+/// Finished episode
+#[derive(Clone, Copy, Debug)]
+pub struct Episode {
+    pub world: usize,
+    pub ret: f64,
+    pub length: u32,
+    pub progress: f64,
+    pub collisions: u32,
+    pub done: Done,
+}
+
+/// The most recent finished episodes of all worlds
+pub struct EpisodeStats {
+    pub episodes: VecDeque<Episode>,
+    pub count: u64,
+    capacity: usize,
+}
+
+impl EpisodeStats {
+    pub fn new(capacity: usize) -> EpisodeStats {
+        EpisodeStats {
+            episodes: VecDeque::with_capacity(capacity),
+            count: 0,
+            capacity: capacity
+        }
+    }
+
+    pub fn push(&mut self, e: Episode) {
+        if self.episodes.len() == self.capacity {
+            self.episodes.pop_front();
+        }
+        self.episodes.push_back(e);
+        self.count += 1;
+    }
+
+    /// Mean return and length of the last `n` episodes
+    pub fn mean(&self, n: usize) -> Option<(f64, f64)> {
+        let n = if n < self.episodes.len() { n } else { self.episodes.len() };
+        if n == 0 {
+            return None;
+        }
+        let (r, l) = self.episodes.iter().rev().take(n)
+            .fold((0.0, 0.0), |(r, l), e| (r + e.ret, l + e.length as f64));
+        Some((r / n as f64, l / n as f64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use env::Done;
+    use geom::Pt;
+    use track::Way;
+    use laps::{LapConfig, LapTracker};
+    use seed;
+
+    fn never() -> EpisodeConfig {
+        let mut config = EpisodeConfig::default();
+        config.termination = TerminationConfig {
+            collision: false,
+            max_stopped_steps: None,
+            max_steps: None,
+            lap: false,
+            max_backwards: None,
+        };
+        config
+    }
+
+    fn square() -> Way {
+        Way::new(&[[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]], 1.0)
+    }
+
+    fn episode(ret: f64) -> Episode {
+        Episode {
+            world: 0,
+            ret: ret,
+            length: ret as u32,
+            progress: 0.0,
+            collisions: 0,
+            done: Done::Timeout,
+        }
+    }
+
+    #[test]
+    fn done_checks_the_conditions_in_order() {
+        let way = square();
+        let mut laps = LapTracker::new(&LapConfig::default(), &way, Pt::new(50.0, 0.0));
+        let mut config = EpisodeConfig::default();
+        config.termination.lap = true;
+        let mut ep = EpisodeState::new();
+        assert!(config.done(&ep, &laps, false).is_none());
+        ep.steps = 5000;
+        assert_eq!(config.done(&ep, &laps, false), Some(Done::Timeout));
+        ep.stopped_steps = 50;
+        assert_eq!(config.done(&ep, &laps, false), Some(Done::Stopped));
+        ep.best_progress = 60.0;
+        assert_eq!(config.done(&ep, &laps, false), Some(Done::Backwards));
+        laps.laps = 1;
+        assert_eq!(config.done(&ep, &laps, false), Some(Done::LapFinished));
+        assert_eq!(config.done(&ep, &laps, true), Some(Done::Collision));
+        assert!(never().done(&ep, &laps, true).is_none());
+    }
+
+    #[test]
+    fn spawn_poses_are_normalized_and_perturbed() {
+        let way = square();
+        let mut config = never();
+        config.spawn = SpawnConfig {
+            poses: vec![[10.0, 0.0, 3.0, 4.0]],
+            random_along_way: false,
+            max_shift: 0.0,
+            max_angle: 0.0,
+        };
+        let mut rng = seed::rng(1, seed::WORLDS);
+        let (center, course) = config.spawn(&way, &mut rng);
+        assert!((center - Pt::new(10.0, 0.0)).norm() < 1e-12);
+        assert!((course - Pt::new(0.6, 0.8)).norm() < 1e-12);
+        config.spawn.max_shift = 2.0;
+        config.spawn.max_angle = 0.5;
+        for _ in 0..100 {
+            let (center, course) = config.spawn(&way, &mut rng);
+            assert!((course.norm() - 1.0).abs() < 1e-12);
+            assert!((course - Pt::new(0.6, 0.8)).norm() < 0.5);
+            assert!((center - Pt::new(10.0, 0.0)).norm() <= 2.0);
+        }
+        config.spawn.random_along_way = true;
+        for _ in 0..100 {
+            let (_, course) = config.spawn(&way, &mut rng);
+            assert!((course.norm() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn stats_keep_the_latest_episodes() {
+        let mut stats = EpisodeStats::new(3);
+        assert!(stats.mean(10).is_none());
+        for i in 1..6 {
+            stats.push(episode(i as f64));
+        }
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.episodes.len(), 3);
+        assert_eq!(stats.episodes.front().unwrap().ret, 3.0);
+        assert_eq!(stats.mean(2), Some((4.5, 4.5)));
+        assert_eq!(stats.mean(10), Some((4.0, 4.0)));
+        assert!(stats.mean(0).is_none());
+    }
+}
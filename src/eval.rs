@@ -0,0 +1,137 @@
+//! Evaluation of the greedy policy: the learner acts with its mean
+//! action and is not updated, so the result measures the policy
+//! itself rather than the policy with exploration noise.
+use std::path;
+use config::TrackConfig;
+use episode::SpawnConfig;
+
+/// What to evaluate on. Without overrides the track, the spawn
+/// poses and the termination of the experiment are used.
+#[derive(Clone, Debug)]
+pub struct EvalOptions {
+    pub episodes: usize,
+    /// Seed of the spawn poses; the same seed gives the same poses
+    pub seed: u64,
+    pub track: Option<TrackConfig>,
+    pub spawn: Option<SpawnConfig>,
+    /// Replaces the step limit of the termination
+    pub max_steps: Option<u32>,
+    /// Directory to write the recordings of the episodes to
+    pub record: Option<path::PathBuf>,
+}
+
+impl EvalOptions {
+    pub fn new(episodes: usize, seed: u64) -> EvalOptions {
+        EvalOptions {
+            episodes: episodes,
+            seed: seed,
+            track: None,
+            spawn: None,
+            max_steps: None,
+            record: None,
+        }
+    }
+}
+
+/// Episode driven by the greedy policy
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct EvalEpisode {
+    pub ret: f64,
+    pub steps: u32,
+    /// Steps of each completed lap
+    pub lap_times: Vec<u32>,
+    /// Times the car ran into a wall
+    pub collisions: u32,
+    /// Distance along the way at the end of the episode
+    pub progress: f64,
+    /// Distance driven by the car
+    pub distance: f64,
+    /// Why the episode has finished
+    pub done: String,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct EvalReport {
+    pub episodes: Vec<EvalEpisode>,
+    pub mean_return: f64,
+    pub std_return: f64,
+    pub laps: usize,
+    /// Mean steps of a lap, if any lap was completed
+    pub mean_lap_time: Option<f64>,
+    pub best_lap_time: Option<u32>,
+    pub collisions: u32,
+    pub mean_progress: f64,
+    pub mean_distance: f64,
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    if xs.is_empty() { 0.0 } else { xs.iter().fold(0.0, |s, x| s + x) / xs.len() as f64 }
+}
+
+impl EvalReport {
+    pub fn new(episodes: Vec<EvalEpisode>) -> EvalReport {
+        let returns: Vec<f64> = episodes.iter().map(|e| e.ret).collect();
+        let mean_return = mean(&returns);
+        let var = mean(&returns.iter().map(|r| (r - mean_return).powi(2)).collect::<Vec<f64>>());
+        let lap_times: Vec<u32> = episodes.iter()
+            .flat_map(|e| e.lap_times.iter().cloned())
+            .collect();
+        let progress: Vec<f64> = episodes.iter().map(|e| e.progress).collect();
+        let distance: Vec<f64> = episodes.iter().map(|e| e.distance).collect();
+        EvalReport {
+            mean_return: mean_return,
+            std_return: var.sqrt(),
+            laps: lap_times.len(),
+            mean_lap_time: if lap_times.is_empty() {
+                None
+            } else {
+                Some(mean(&lap_times.iter().map(|&t| t as f64).collect::<Vec<f64>>()))
+            },
+            best_lap_time: lap_times.iter().cloned().min(),
+            collisions: episodes.iter().fold(0, |s, e| s + e.collisions),
+            mean_progress: mean(&progress),
+            mean_distance: mean(&distance),
+            episodes: episodes,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut s = format!("episodes: {}, return: {:.2} ± {:.2}, laps: {}",
+                            self.episodes.len(), self.mean_return, self.std_return, self.laps);
+        if let (Some(mean), Some(best)) = (self.mean_lap_time, self.best_lap_time) {
+            s.push_str(&format!(", lap time: {:.1} (best {})", mean, best));
+        }
+        s.push_str(&format!(", collisions: {}, progress: {:.1}, distance: {:.1}",
+                            self.collisions, self.mean_progress, self.mean_distance));
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn episode(ret: f64, lap_times: Vec<u32>) -> EvalEpisode {
+        EvalEpisode {
+            ret: ret,
+            steps: 100,
+            lap_times: lap_times,
+            collisions: 1,
+            progress: 10.0,
+            distance: 12.0,
+            done: "Timeout".to_string()
+        }
+    }
+
+    #[test]
+    fn report_aggregates_episodes() {
+        let report = EvalReport::new(vec![episode(1.0, vec![]),
+                                          episode(3.0, vec![40, 30])]);
+        assert_eq!(report.mean_return, 2.0);
+        assert_eq!(report.std_return, 1.0);
+        assert_eq!(report.laps, 2);
+        assert_eq!(report.mean_lap_time, Some(35.0));
+        assert_eq!(report.best_lap_time, Some(30));
+        assert_eq!(report.collisions, 2);
+    }
+}
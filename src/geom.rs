@@ -1,4 +1,4 @@
-use std::{self, env};
+use std;
 use std::ops::{Add, Sub, Mul};
 
 #[derive(Clone, Copy, Debug)]
@@ -97,6 +97,60 @@ impl Sect {
             p1: p1
         }
     }
+
+    pub fn bbox(&self) -> BBox {
+        let mut b = BBox::empty();
+        b.add(self.p0);
+        b.add(self.p1);
+        b
+    }
+}
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug)]
+pub struct BBox {
+    pub lo: Pt,
+    pub hi: Pt,
+}
+
+impl BBox {
+    pub fn empty() -> BBox {
+        BBox {
+            lo: Pt::new(1.0e20, 1.0e20),
+            hi: Pt::new(-1.0e20, -1.0e20)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lo.x > self.hi.x || self.lo.y > self.hi.y
+    }
+
+    pub fn add(&mut self, p: Pt) {
+        self.lo.x = self.lo.x.min(p.x);
+        self.lo.y = self.lo.y.min(p.y);
+        self.hi.x = self.hi.x.max(p.x);
+        self.hi.y = self.hi.y.max(p.y);
+    }
+
+    pub fn union(&self, b: &BBox) -> BBox {
+        BBox {
+            lo: Pt::new(self.lo.x.min(b.lo.x), self.lo.y.min(b.lo.y)),
+            hi: Pt::new(self.hi.x.max(b.hi.x), self.hi.y.max(b.hi.y))
+        }
+    }
+
+    pub fn expanded(&self, d: f64) -> BBox {
+        BBox {
+            lo: self.lo - Pt::new(d, d),
+            hi: self.hi + Pt::new(d, d)
+        }
+    }
+
+    /// Boxes touching each other are considered intersecting
+    pub fn intersects(&self, b: &BBox) -> bool {
+        self.lo.x <= b.hi.x && b.lo.x <= self.hi.x
+            && self.lo.y <= b.hi.y && b.lo.y <= self.hi.y
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -131,6 +185,10 @@ impl Path {
             sects: sects.clone()
         }
     }
+
+    pub fn bbox(&self) -> BBox {
+        self.sects.iter().fold(BBox::empty(), |b, s| b.union(&s.bbox()))
+    }
 }
 
 #[derive(Debug)]
@@ -176,6 +234,10 @@ impl Figure {
             paths: paths
         }
     }
+
+    pub fn bbox(&self) -> BBox {
+        self.paths.iter().fold(BBox::empty(), |b, p| b.union(&p.bbox()))
+    }
 }
 
 //TODO: use (-) operator
@@ -211,11 +273,47 @@ fn sections_intersect(subj: &Sect, obj: &Sect, is_ray: bool) -> Isx {
     isx
 }
 
+pub fn point_segment_distance(p: Pt, a: Pt, b: Pt) -> f64 {
+    let d = b - a;
+    let l2 = d.x * d.x + d.y * d.y;
+    let t = if l2 > 0.0 { ((p - a).x * d.x + (p - a).y * d.y) / l2 } else { 0.0 };
+    let t = t.max(0.0).min(1.0);
+    (p - (a + t * d)).norm()
+}
+
+/// Whether the segments cross at a point inside both of them
+pub fn segments_cross(a0: Pt, a1: Pt, b0: Pt, b1: Pt) -> bool {
+    vdot(&(a1 - a0), &(b0 - a0)) * vdot(&(a1 - a0), &(b1 - a0)) < 0.0
+        && vdot(&(b1 - b0), &(a0 - b0)) * vdot(&(b1 - b0), &(a1 - b0)) < 0.0
+}
+
+pub fn segments_distance(a0: Pt, a1: Pt, b0: Pt, b1: Pt) -> f64 {
+    if segments_cross(a0, a1, b0, b1) {
+        return 0.0;
+    }
+    point_segment_distance(a0, b0, b1)
+        .min(point_segment_distance(a1, b0, b1))
+        .min(point_segment_distance(b0, a0, a1))
+        .min(point_segment_distance(b1, a0, a1))
+}
+
+/// Whether the figures cross, by checking every pair of paths;
+/// what the grid is tested against
+#[cfg(test)]
 pub fn figures_intersect(subjs: &Figure, objs: &Figure) -> bool {
+    let obj_bboxes = objs.paths.iter().map(|p| p.bbox()).collect::<Vec<BBox>>();
     for p1 in subjs.paths.iter() {
+        let pb = p1.bbox();
         for s in p1.sects.iter() {
-            for p2 in objs.paths.iter() {
+            let sb = s.bbox();
+            for (p2, ob) in objs.paths.iter().zip(obj_bboxes.iter()) {
+                if !pb.intersects(ob) {
+                    continue;
+                }
                 for o in p2.sects.iter() {
+                    if !sb.intersects(&o.bbox()) {
+                        continue;
+                    }
                     let isx = sections_intersect(s, o, false);
                     if isx.dist >= 0.0 {
                         //println!("isx: s={:?}, o={:?}, isx={:?}", s, o, isx);
@@ -253,6 +351,249 @@ pub fn rays_figure_intersections(rays: &[Sect],
     }
 }
 
+/// Uniform grid over the sections of a figure.
+///
+/// Every cell keeps the indices of the sections passing through it,
+/// so a ray only has to be tested against the sections of the cells
+/// it crosses, and a moving figure only against the sections near it.
+/// The grid is immutable and is meant to be built once per track
+/// and shared between cars.
+pub struct Grid {
+    pub sects: Vec<Sect>,
+    bboxes: Vec<BBox>,
+    cells: Vec<Vec<usize>>,
+    origin: Pt,
+    cell: f64,
+    nx: usize,
+    ny: usize,
+}
+
+impl Grid {
+    pub fn new(figure: &Figure, cell: f64) -> Grid {
+        let mut sects = Vec::new();
+        for p in figure.paths.iter() {
+            sects.extend_from_slice(p.sects.as_ref());
+        }
+        if sects.is_empty() {
+            return Grid {
+                sects: sects,
+                bboxes: Vec::new(),
+                cells: Vec::new(),
+                origin: Pt::zero(),
+                cell: cell,
+                nx: 0,
+                ny: 0
+            }
+        }
+
+        let bbox = figure.bbox();
+        let lo = bbox.lo;
+        let hi = bbox.hi;
+        // Pad the grid a little, so that no section lies on its border
+        let eps = GRID_EPS * cell;
+        let origin = lo - Pt::new(eps, eps);
+        let nx = ((hi.x - origin.x + eps) / cell).ceil().max(1.0) as usize;
+        let ny = ((hi.y - origin.y + eps) / cell).ceil().max(1.0) as usize;
+
+        let mut grid = Grid {
+            sects: Vec::new(),
+            bboxes: sects.iter().map(|s| s.bbox()).collect(),
+            cells: vec![Vec::new(); nx * ny],
+            origin: origin,
+            cell: cell,
+            nx: nx,
+            ny: ny
+        };
+        for (i, s) in sects.iter().enumerate() {
+            let b = grid.bboxes[i].expanded(eps);
+            let (x0, y0) = grid.cell_of(b.lo);
+            let (x1, y1) = grid.cell_of(b.hi);
+            for iy in y0..y1+1 {
+                for ix in x0..x1+1 {
+                    if grid.sect_touches_cell(s, ix, iy) {
+                        grid.cells[iy * nx + ix].push(i);
+                    }
+                }
+            }
+        }
+        grid.sects = sects;
+        grid
+    }
+
+    fn cell_of(&self, p: Pt) -> (usize, usize) {
+        let ix = ((p.x - self.origin.x) / self.cell).floor();
+        let iy = ((p.y - self.origin.y) / self.cell).floor();
+        (clamp_index(ix, self.nx), clamp_index(iy, self.ny))
+    }
+
+    // Conservative test: the section touches the (slightly padded) cell
+    // unless all the cell corners lie strictly on one side of its line.
+    fn sect_touches_cell(&self, s: &Sect, ix: usize, iy: usize) -> bool {
+        let eps = GRID_EPS * self.cell;
+        let x0 = self.origin.x + ix as f64 * self.cell - eps;
+        let y0 = self.origin.y + iy as f64 * self.cell - eps;
+        let x1 = x0 + self.cell + 2.0 * eps;
+        let y1 = y0 + self.cell + 2.0 * eps;
+        let d = s.p1 - s.p0;
+        let mut pos = false;
+        let mut neg = false;
+        for c in [Pt::new(x0, y0), Pt::new(x1, y0),
+                  Pt::new(x1, y1), Pt::new(x0, y1)].iter() {
+            let v = vdot(&d, &(*c - s.p0));
+            if v >= 0.0 {
+                pos = true;
+            }
+            if v <= 0.0 {
+                neg = true;
+            }
+        }
+        pos && neg
+    }
+
+    /// Broad-phase version of `figures_intersect(figure, walls)`:
+    /// the exact test is run only for the wall sections from the cells
+    /// overlapped by the bounding box of each section of the figure.
+    pub fn intersects_figure(&self, figure: &Figure) -> bool {
+        if self.cells.is_empty() {
+            return false;
+        }
+        let eps = GRID_EPS * self.cell;
+        for p in figure.paths.iter() {
+            for s in p.sects.iter() {
+                let sb = s.bbox();
+                let b = sb.expanded(eps);
+                let (x0, y0) = self.cell_of(b.lo);
+                let (x1, y1) = self.cell_of(b.hi);
+                for iy in y0..y1+1 {
+                    for ix in x0..x1+1 {
+                        for &i in self.cells[iy * self.nx + ix].iter() {
+                            if !sb.intersects(&self.bboxes[i]) {
+                                continue;
+                            }
+                            if sections_intersect(s, &self.sects[i], false).dist >= 0.0 {
+                                return true
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Nearest intersection of the ray (`p0` is the origin,
+    /// `p1` is the direction) with the sections of the grid.
+    /// If there is none, the distance of the result is 1.0e20.
+    pub fn ray_intersection(&self, ray: &Sect) -> Isx {
+        let mut min_isx = Isx{point: Pt{x: 0.0, y: 0.0}, dist: 1.0e20};
+        let o = ray.p0;
+        let d = ray.p1;
+        let dn = d.norm();
+        if self.cells.is_empty() || dn == 0.0 {
+            return min_isx;
+        }
+
+        // Clip the ray by the bounds of the grid
+        let w = self.nx as f64 * self.cell;
+        let h = self.ny as f64 * self.cell;
+        let (tx0, tx1) = slab(o.x, d.x, self.origin.x, self.origin.x + w);
+        let (ty0, ty1) = slab(o.y, d.y, self.origin.y, self.origin.y + h);
+        let t_enter = tx0.max(ty0).max(0.0);
+        let t_exit = tx1.min(ty1);
+        if t_enter > t_exit {
+            return min_isx;
+        }
+
+        let (mut ix, mut iy) = self.cell_of(o + t_enter * d);
+        let (step_x, mut t_max_x, t_delta_x) =
+            dda_axis(o.x, d.x, self.origin.x, ix, self.cell);
+        let (step_y, mut t_max_y, t_delta_y) =
+            dda_axis(o.y, d.y, self.origin.y, iy, self.cell);
+
+        loop {
+            for &i in self.cells[iy * self.nx + ix].iter() {
+                let isx = sections_intersect(ray, &self.sects[i], true);
+                if isx.dist >= 0.0 && isx.dist < min_isx.dist {
+                    min_isx = isx;
+                }
+            }
+            // Every section crossing the ray before the exit from this
+            // cell has been checked already, so nothing closer is left
+            let t_cell_exit = t_max_x.min(t_max_y);
+            if min_isx.dist <= t_cell_exit * dn {
+                break;
+            }
+            if t_max_x < t_max_y {
+                if (step_x < 0 && ix == 0) || (step_x > 0 && ix + 1 == self.nx) {
+                    break;
+                }
+                ix = (ix as isize + step_x) as usize;
+                t_max_x += t_delta_x;
+            } else {
+                if (step_y < 0 && iy == 0) || (step_y > 0 && iy + 1 == self.ny) {
+                    break;
+                }
+                iy = (iy as isize + step_y) as usize;
+                t_max_y += t_delta_y;
+            }
+        }
+        min_isx
+    }
+}
+
+const GRID_EPS: f64 = 1.0e-6;
+
+fn clamp_index(i: f64, n: usize) -> usize {
+    if i < 0.0 {
+        0
+    } else if i >= n as f64 {
+        n - 1
+    } else {
+        i as usize
+    }
+}
+
+// Parameter interval of the ray `o + t*d` inside `lo..hi` along one axis
+fn slab(o: f64, d: f64, lo: f64, hi: f64) -> (f64, f64) {
+    if d == 0.0 {
+        if o < lo || o > hi {
+            (1.0e20, -1.0e20)
+        } else {
+            (-1.0e20, 1.0e20)
+        }
+    } else {
+        let t0 = (lo - o) / d;
+        let t1 = (hi - o) / d;
+        (t0.min(t1), t0.max(t1))
+    }
+}
+
+// Step direction, parameter of the first cell boundary and parameter
+// distance between boundaries for the grid traversal along one axis
+fn dda_axis(o: f64, d: f64, origin: f64, i: usize, cell: f64) -> (isize, f64, f64) {
+    if d > 0.0 {
+        (1, (origin + (i + 1) as f64 * cell - o) / d, cell / d)
+    } else if d < 0.0 {
+        (-1, (origin + i as f64 * cell - o) / d, -cell / d)
+    } else {
+        (0, 1.0e20, 1.0e20)
+    }
+}
+
+pub fn rays_grid_intersections(rays: &[Sect],
+                               grid: &Grid,
+                               infinity: f64,
+                               intersections: &mut[Isx]) {
+    for (i, r) in rays.iter().enumerate() {
+        let min_isx = grid.ray_intersection(r);
+        if min_isx.dist < 0.0 {
+            intersections[i].dist = infinity;
+        } else {
+            intersections[i] = min_isx;
+        }
+    }
+}
+
 pub fn recalc_rays(rays: &mut[Sect], center: Pt, course: Pt) {
     let k = 2.0 * std::f64::consts::PI / (rays.len() as f64);
     let rn = rays.len();
@@ -287,3 +628,65 @@ fn main() {
     perftest(f);
 }
 */
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use track::{clover, make_track};
+    use rand::{Rng, SeedableRng, StdRng};
+
+    fn brute_and_grid(walls: &Figure, cell: f64, rng: &mut StdRng, lo: f64, hi: f64) {
+        let grid = Grid::new(walls, cell);
+        let mut rays = vec![Sect::zero(); 36];
+        let mut brute = vec![Isx::zero(); 36];
+        let mut fast = vec![Isx::zero(); 36];
+        for _ in 0..200 {
+            let center = Pt::new(rng.gen_range(lo, hi), rng.gen_range(lo, hi));
+            let angle = rng.gen_range(0.0, 2.0 * std::f64::consts::PI);
+            recalc_rays(rays.as_mut(), center, Pt::new(angle.cos(), angle.sin()));
+            rays_figure_intersections(&rays, walls, -1.0, brute.as_mut());
+            rays_grid_intersections(&rays, &grid, -1.0, fast.as_mut());
+            for i in 0..rays.len() {
+                assert_eq!(brute[i].dist, fast[i].dist);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_rays_match_brute_force() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[1usize][..]);
+        let walls = clover(4.0, 10.0);
+        for &cell in [0.7, 5.0, 30.0, 1000.0].iter() {
+            brute_and_grid(&walls, cell, &mut rng, -150.0, 150.0);
+        }
+
+        let n = 2000;
+        let circle = (0..n).map(|i| {
+            let a = 2.0 * std::f64::consts::PI * (i as f64) / (n as f64);
+            [100.0 * a.cos(), 100.0 * a.sin() * (1.0 + 0.3 * (5.0 * a).sin())]
+        }).collect::<Vec<[f64; 2]>>();
+        let walls = make_track(circle.as_ref(), 4.0, 1.0);
+        brute_and_grid(&walls, 2.0, &mut rng, -150.0, 150.0);
+    }
+
+    #[test]
+    fn grid_collisions_match_brute_force() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[2usize][..]);
+        let walls = clover(4.0, 10.0);
+        let grid = Grid::new(&walls, 5.0);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let c = Pt::new(rng.gen_range(-130.0, 130.0), rng.gen_range(-130.0, 130.0));
+            let a = rng.gen_range(0.0, 2.0 * std::f64::consts::PI);
+            let l = 1.5 * Pt::new(a.cos(), a.sin());
+            let w = 0.8 * Pt::new(a.sin(), -a.cos());
+            let car = Figure::closed_path(&[c + l - w, c + l + w, c - l + w, c - l - w]);
+            let brute = figures_intersect(&car, &walls);
+            assert_eq!(brute, grid.intersects_figure(&car));
+            if brute {
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -0,0 +1,247 @@
+//! Laps and checkpoints. The checkpoints are gates across the way,
+//! evenly spaced from where the episode starts, and the car has to
+//! drive through them in order: a shortcut past a gate leaves it
+//! behind, and the next ones do not count until the car returns
+//! to it. The last gate of a lap is the start line.
+use geom::Pt;
+use track::Way;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct LapConfig {
+    /// Gates along a lap, the start line included; at least 2
+    pub checkpoints: usize,
+    /// How far beyond the walls a gate reaches
+    pub margin: f64,
+}
+
+impl LapConfig {
+    pub fn default() -> LapConfig {
+        LapConfig {
+            checkpoints: 8,
+            margin: 2.0,
+        }
+    }
+
+    /// Fails with fewer than two checkpoints, as the start line
+    /// alone could be driven through back and forth for laps
+    pub fn check(&self) -> Result<(), String> {
+        if self.checkpoints < 2 {
+            return Err(format!("Laps need at least 2 checkpoints, got {}", self.checkpoints));
+        }
+        Ok(())
+    }
+}
+
+// Line across the way through `center`, normal to `direction`,
+// that reaches as far as `reach` from the center
+#[derive(Clone, Copy, Debug)]
+struct Gate {
+    center: Pt,
+    direction: Pt,
+    reach: f64,
+}
+
+impl Gate {
+    // 1 if the car drove through the gate forward, -1 if backward
+    fn crossing(&self, from: Pt, to: Pt) -> i32 {
+        let dot = |p: Pt| (p.x - self.center.x) * self.direction.x + (p.y - self.center.y) * self.direction.y;
+        let (s0, s1) = (dot(from), dot(to));
+        if (s0 < 0.0) == (s1 < 0.0) {
+            return 0;
+        }
+        let x = from + s0 / (s0 - s1) * (to - from);
+        if (x - self.center).norm() > self.reach {
+            0
+        } else if s1 >= 0.0 {
+            1
+        } else {
+            -1
+        }
+    }
+}
+
+/// Progress of a car in its episode. Times are in steps.
+#[derive(Clone, Debug)]
+pub struct LapTracker {
+    gates: Vec<Gate>,
+    /// Index of the gate to drive through next
+    pub next_checkpoint: usize,
+    /// Checkpoints passed at the last step
+    pub passed: u32,
+    pub laps: u32,
+    pub steps: u32,
+    lap_start: u32,
+    pub last_lap: Option<u32>,
+    pub best_lap: Option<u32>,
+    /// Steps in a row the car has driven back along the way
+    pub wrong_way_steps: u32,
+}
+
+impl LapTracker {
+    /// Tracker of an episode starting at `center`. On an open way
+    /// the gates are spaced up to its end, and a lap ends there;
+    /// there are no gates, and no laps, if it starts at the end.
+    /// A gate reaches across the way to the walls and a margin beyond.
+    pub fn new(config: &LapConfig, way: &Way, center: Pt) -> LapTracker {
+        let start = way.distance(&way.where_is(center));
+        let length = if way.is_closed() { way.length() } else { way.length() - start };
+        let n = if length > 0.0 { config.checkpoints.max(1) } else { 0 };
+        let gates = (1..n + 1).map(|k| {
+            let wp = way.at_distance(start + length * k as f64 / n as f64);
+            Gate {
+                center: way.point(&wp),
+                direction: way.direction(&wp),
+                reach: way.half_width(&wp) + config.margin,
+            }
+        }).collect();
+        LapTracker {
+            gates: gates,
+            next_checkpoint: 0,
+            passed: 0,
+            laps: 0,
+            steps: 0,
+            lap_start: 0,
+            last_lap: None,
+            best_lap: None,
+            wrong_way_steps: 0,
+        }
+    }
+
+    pub fn checkpoints(&self) -> usize {
+        self.gates.len()
+    }
+
+    /// Counts the step of the car from `from` to `to`, `offset` along the way
+    pub fn step(&mut self, from: Pt, to: Pt, offset: f64) {
+        self.steps += 1;
+        self.wrong_way_steps = if offset < 0.0 { self.wrong_way_steps + 1 } else { 0 };
+        self.passed = 0;
+        let n = self.gates.len();
+        if n == 0 {
+            return;
+        }
+        if self.gates[self.next_checkpoint].crossing(from, to) > 0 {
+            self.passed = 1;
+            self.next_checkpoint += 1;
+            if self.next_checkpoint == n {
+                self.next_checkpoint = 0;
+                self.laps += 1;
+                let time = self.steps - self.lap_start;
+                self.lap_start = self.steps;
+                self.last_lap = Some(time);
+                self.best_lap = Some(self.best_lap.map_or(time, |b| b.min(time)));
+            }
+        } else if self.next_checkpoint > 0
+            && self.gates[self.next_checkpoint - 1].crossing(from, to) < 0 {
+            // Back through the last gate, which has to be passed again
+            self.next_checkpoint -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use geom::Pt;
+    use track::Way;
+
+    // Drives along the points in steps of about `step`
+    fn drive(tracker: &mut LapTracker, way: &Way, points: &[Pt], step: f64) {
+        for w in points.windows(2) {
+            let n = ((w[1] - w[0]).norm() / step).ceil() as usize;
+            for k in 0..n {
+                let from = w[0] + k as f64 / n as f64 * (w[1] - w[0]);
+                let to = w[0] + (k + 1) as f64 / n as f64 * (w[1] - w[0]);
+                let offset = way.offset(&way.where_is(from), &way.where_is(to));
+                tracker.step(from, to, offset);
+            }
+        }
+    }
+
+    fn square() -> (Way, Vec<Pt>) {
+        let way = Way::new(&[[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]], 1.0);
+        let lap = vec![Pt::new(10.0, 0.0), Pt::new(100.0, 0.0), Pt::new(100.0, 100.0),
+                       Pt::new(0.0, 100.0), Pt::new(0.0, 0.0), Pt::new(10.0, 0.0)];
+        (way, lap)
+    }
+
+    #[test]
+    fn laps_are_counted_and_timed() {
+        let (way, lap) = square();
+        let mut tracker = LapTracker::new(&LapConfig::default(), &way, lap[0]);
+        drive(&mut tracker, &way, &lap, 1.0);
+        assert_eq!(tracker.laps, 1);
+        assert_eq!(tracker.next_checkpoint, 0);
+        assert_eq!(tracker.last_lap, Some(400));
+        drive(&mut tracker, &way, &lap, 2.0);
+        assert_eq!(tracker.laps, 2);
+        assert_eq!(tracker.last_lap, Some(200));
+        assert_eq!(tracker.best_lap, Some(200));
+        assert_eq!(tracker.wrong_way_steps, 0);
+    }
+
+    #[test]
+    fn gates_reach_across_wide_tracks() {
+        let (way, _) = square();
+        let way = way.with_half_widths(vec![20.0; 4]);
+        // Near the outer wall all the way round
+        let lap = [Pt::new(10.0, -15.0), Pt::new(115.0, -15.0), Pt::new(115.0, 115.0),
+                   Pt::new(-15.0, 115.0), Pt::new(-15.0, -15.0), Pt::new(10.0, -15.0)];
+        let mut tracker = LapTracker::new(&LapConfig::default(), &way, lap[0]);
+        drive(&mut tracker, &way, &lap, 1.0);
+        assert_eq!(tracker.laps, 1);
+    }
+
+    #[test]
+    fn shortcuts_do_not_count() {
+        let (way, _) = square();
+        let mut tracker = LapTracker::new(&LapConfig::default(), &way, Pt::new(10.0, 0.0));
+        // Across the middle, past the gates of the second side
+        let cut = [Pt::new(10.0, 0.0), Pt::new(70.0, 0.0), Pt::new(70.0, 100.0),
+                   Pt::new(0.0, 100.0), Pt::new(0.0, 0.0), Pt::new(10.0, 0.0)];
+        let mut checkpoints = 0;
+        for w in cut.windows(2) {
+            let n = (w[1] - w[0]).norm().ceil() as usize;
+            for k in 0..n {
+                let from = w[0] + k as f64 / n as f64 * (w[1] - w[0]);
+                let to = w[0] + (k + 1) as f64 / n as f64 * (w[1] - w[0]);
+                tracker.step(from, to, 0.0);
+                checkpoints += tracker.passed;
+            }
+        }
+        assert_eq!(tracker.laps, 0);
+        assert_eq!(checkpoints, 1);
+        assert_eq!(tracker.next_checkpoint, 1);
+    }
+
+    #[test]
+    fn driving_back_through_a_gate_undoes_it() {
+        let (way, _) = square();
+        let mut tracker = LapTracker::new(&LapConfig::default(), &way, Pt::new(10.0, 0.0));
+        drive(&mut tracker, &way, &[Pt::new(10.0, 0.0), Pt::new(70.0, 0.0)], 1.0);
+        assert_eq!(tracker.next_checkpoint, 1);
+        drive(&mut tracker, &way, &[Pt::new(70.0, 0.0), Pt::new(40.0, 0.0)], 1.0);
+        assert_eq!(tracker.next_checkpoint, 0);
+        assert_eq!(tracker.wrong_way_steps, 30);
+    }
+
+    #[test]
+    fn single_checkpoint_is_refused() {
+        let mut config = LapConfig::default();
+        assert!(config.check().is_ok());
+        config.checkpoints = 1;
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn no_laps_from_the_end_of_an_open_way() {
+        let way = Way::open(&[[0.0, 0.0], [100.0, 0.0], [100.0, 100.0]], 1.0);
+        let end = Pt::new(100.0, 100.0);
+        let mut tracker = LapTracker::new(&LapConfig::default(), &way, end);
+        assert_eq!(tracker.checkpoints(), 0);
+        drive(&mut tracker, &way, &[end, Pt::new(100.0, 50.0), end], 1.0);
+        assert_eq!(tracker.laps, 0);
+        assert_eq!(tracker.steps, 100);
+        assert_eq!(tracker.wrong_way_steps, 0);
+    }
+}
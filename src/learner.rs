@@ -0,0 +1,94 @@
+use std::path;
+use cacla::{Cacla, Range};
+use ddpg::{Ddpg, DdpgConfig};
+use ppo::{Ppo, PpoConfig};
+use config::LearnerConfig;
+use checkpoint::Layers;
+
+/// Learning algorithm driven by the `Polygon` training loop.
+/// States are normalized. Actions are nominally in -1..1, but
+/// exploration noise may take them outside: only DDPG clamps them.
+/// `world` is the index of the world the state comes from.
+pub trait Learner {
+    /// Action to take in the state, with exploration noise
+    fn get_action(&mut self, world: usize, state: &Vec<f64>, wander_more: bool) -> Vec<f64>;
+
+    /// Learns from the transition. The value of a terminal
+    /// new state is zero: nothing is bootstrapped from it.
+    fn step(&mut self, world: usize, old_state: &Vec<f64>, new_state: &Vec<f64>,
+            action: &Vec<f64>, reward: f64, terminal: bool);
+
+    /// The world starts a new episode after its last step,
+    /// whether that step was terminal or not
+    fn episode_end(&mut self, _world: usize) {}
+
+    /// Writes the state and the networks into the checkpoint directory
+    fn save(&self, dir: &path::Path) -> Result<(), String>;
+
+    fn load(&mut self, dir: &path::Path) -> Result<(), String>;
+
+    /// Names and layer sizes of the networks
+    fn networks(&self) -> Vec<Layers>;
+
+    /// Standard deviation of the exploration noise
+    fn sigma(&self) -> f64;
+
+    /// Statistics of the learning worth logging, such as the
+    /// variance of the TD error
+    fn metrics(&self) -> Vec<(String, f64)> {
+        Vec::new()
+    }
+
+    /// Value of a state
+    fn v_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>>;
+
+    /// Action without exploration noise
+    fn ac_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>>;
+
+    fn print(&self);
+}
+
+/// Creates the learner selected by `config.algorithm`:
+/// "cacla", "ddpg", "td3" or "ppo", for `worlds` worlds
+pub fn build(config: &LearnerConfig, state_ranges: &Vec<Range>,
+             action_dim: usize, worlds: usize, seed: u64) -> Result<Box<Learner>, String> {
+    match config.algorithm.as_ref() {
+        "cacla" => {
+            let cacla = try!(Cacla::new(state_ranges,
+                                        action_dim as u32,
+                                        &config.approx,
+                                        config.gamma,
+                                        config.alpha,
+                                        config.beta,
+                                        config.sigma,
+                                        config.replay.as_ref(),
+                                        seed));
+            Ok(Box::new(cacla))
+        },
+        "ddpg" | "td3" => {
+            let dc = config.ddpg.clone().unwrap_or_else(DdpgConfig::default);
+            let ddpg = try!(Ddpg::new(state_ranges.len(),
+                                      action_dim,
+                                      &config.approx,
+                                      &dc,
+                                      config.gamma,
+                                      config.sigma,
+                                      config.algorithm == "td3",
+                                      seed));
+            Ok(Box::new(ddpg))
+        },
+        "ppo" => {
+            let pc = config.ppo.clone().unwrap_or_else(PpoConfig::default);
+            let ppo = try!(Ppo::new(state_ranges.len(),
+                                    action_dim,
+                                    worlds,
+                                    &config.approx,
+                                    &pc,
+                                    config.gamma,
+                                    config.sigma,
+                                    seed));
+            Ok(Box::new(ppo))
+        },
+        name => Err(format!("Unknown learner: {}", name))
+    }
+}
@@ -1,15 +1,41 @@
+#[cfg(feature = "fann")]
 extern crate fann;
 extern crate rand;
+extern crate libc;
+#[cfg(feature = "gui")]
 extern crate sfml;
 extern crate rustc_serialize;
 
+mod approx;
+mod mlp;
 mod cacla;
+mod replay;
+mod ddpg;
+mod ppo;
+mod learner;
 mod geom;
 mod car;
 mod track;
+mod trackgen;
+mod trackcheck;
+mod laps;
+#[cfg(feature = "gui")]
 mod polyshape;
+#[cfg(feature = "gui")]
 mod plot;
 pub mod polygon;
+pub mod env;
+pub mod reward;
+pub mod observation;
+pub mod episode;
+pub mod seed;
+pub mod config;
+pub mod checkpoint;
+pub mod eval;
+pub mod metrics;
+pub mod recording;
+pub mod train;
+#[cfg(feature = "gui")]
 pub mod view;
 
 #[cfg(test)]
@@ -0,0 +1,253 @@
+//! Training metrics written to the workspace, a row per interval,
+//! so that runs can be compared after they are over.
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path;
+use rustc_serialize::json;
+use episode::Episode;
+use env::Done;
+
+/// Row of named values
+pub type Row = Vec<(String, f64)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    /// "csv" or "jsonl"
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::Jsonl),
+            _ => None
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match *self {
+            Format::Csv => "metrics.csv",
+            Format::Jsonl => "metrics.jsonl",
+        }
+    }
+}
+
+/// Sums over the steps of all the worlds and over the
+/// episodes they finished since the last row
+pub struct Interval {
+    pub steps: u64,
+    pub reward: f64,
+    /// Unweighted values of the reward terms
+    pub terms: Vec<f64>,
+    pub episodes: u64,
+    pub returns: f64,
+    pub min_return: f64,
+    pub max_return: f64,
+    pub progress: f64,
+    pub collisions: u64,
+    /// Episodes finished by a collision
+    pub crashes: u64,
+}
+
+impl Interval {
+    pub fn new(nterms: usize) -> Interval {
+        Interval {
+            steps: 0,
+            reward: 0.0,
+            terms: vec![0.0; nterms],
+            episodes: 0,
+            returns: 0.0,
+            min_return: 0.0,
+            max_return: 0.0,
+            progress: 0.0,
+            collisions: 0,
+            crashes: 0,
+        }
+    }
+
+    pub fn step(&mut self, reward: f64, terms: &[f64]) {
+        self.steps += 1;
+        self.reward += reward;
+        for (s, t) in self.terms.iter_mut().zip(terms.iter()) {
+            *s += *t;
+        }
+    }
+
+    pub fn episode(&mut self, e: &Episode) {
+        if self.episodes == 0 || e.ret < self.min_return {
+            self.min_return = e.ret;
+        }
+        if self.episodes == 0 || e.ret > self.max_return {
+            self.max_return = e.ret;
+        }
+        self.episodes += 1;
+        self.returns += e.ret;
+        self.progress += e.progress;
+        self.collisions += e.collisions as u64;
+        if e.done == Done::Collision {
+            self.crashes += 1;
+        }
+    }
+
+    /// Means of the interval; those of the episodes are NaN
+    /// if no episode has finished
+    pub fn row(&self, term_names: &[&str]) -> Row {
+        let steps = if self.steps > 0 { self.steps as f64 } else { 1.0 };
+        let episodes = if self.episodes > 0 { self.episodes as f64 } else { ::std::f64::NAN };
+        let mut row = vec![("steps".to_string(), self.steps as f64),
+                           ("reward".to_string(), self.reward / steps)];
+        for (name, t) in term_names.iter().zip(self.terms.iter()) {
+            row.push((format!("term_{}", name), t / steps));
+        }
+        row.push(("episodes".to_string(), self.episodes as f64));
+        row.push(("mean_return".to_string(), self.returns / episodes));
+        row.push(("min_return".to_string(), if self.episodes > 0 { self.min_return } else { ::std::f64::NAN }));
+        row.push(("max_return".to_string(), if self.episodes > 0 { self.max_return } else { ::std::f64::NAN }));
+        row.push(("mean_progress".to_string(), self.progress / episodes));
+        row.push(("collisions".to_string(), self.collisions as f64));
+        row.push(("crashes".to_string(), self.crashes as f64));
+        row
+    }
+}
+
+/// Appends rows to the metrics file of a workspace. A CSV file
+/// gets its header when it is created. A resumed run keeps appending
+/// to it if its rows have the same columns; otherwise the old file is
+/// renamed to metrics.1.csv (or the next free number) and a new one
+/// is started.
+pub struct MetricsLog {
+    file: File,
+    filename: path::PathBuf,
+    format: Format,
+    /// Header the rows have to match, if the file has one
+    header: Option<String>,
+}
+
+impl MetricsLog {
+    pub fn open(ws_dir: &path::Path, format: Format) -> Result<MetricsLog, String> {
+        let filename = ws_dir.join(format.file_name());
+        let file = try!(MetricsLog::append(&filename));
+        let mut header = None;
+        if format == Format::Csv {
+            let mut content = String::new();
+            try!(File::open(&filename).and_then(|mut f| f.read_to_string(&mut content))
+                 .map_err(|e| format!("Cannot read {:?}: {}", filename, e)));
+            header = content.lines().next().map(|l| l.to_string());
+        }
+        Ok(MetricsLog {
+            file: file,
+            filename: filename,
+            format: format,
+            header: header,
+        })
+    }
+
+    fn append(filename: &path::Path) -> Result<File, String> {
+        OpenOptions::new().create(true).append(true).open(filename)
+            .map_err(|e| format!("Cannot open {:?}: {}", filename, e))
+    }
+
+    // Moves the file away to the first free numbered name
+    // and starts a new one
+    fn start_new_file(&mut self) -> Result<(), String> {
+        let stem = self.filename.file_stem().unwrap().to_string_lossy().into_owned();
+        let ext = self.filename.extension().unwrap().to_string_lossy().into_owned();
+        let mut n = 1;
+        let old = loop {
+            let old = self.filename.with_file_name(format!("{}.{}.{}", stem, n, ext));
+            if !old.exists() {
+                break old;
+            }
+            n += 1;
+        };
+        try!(fs::rename(&self.filename, &old)
+             .map_err(|e| format!("Cannot rename {:?}: {}", self.filename, e)));
+        println!("Metrics columns have changed, {:?} is moved to {:?}", self.filename, old);
+        self.file = try!(MetricsLog::append(&self.filename));
+        Ok(())
+    }
+
+    pub fn write(&mut self, row: &Row) -> Result<(), String> {
+        let mut line = String::new();
+        match self.format {
+            Format::Csv => {
+                let names: Vec<&str> = row.iter().map(|&(ref n, _)| n.as_ref()).collect();
+                let names = names.join(",");
+                if self.header.as_ref() != Some(&names) {
+                    if self.header.is_some() {
+                        try!(self.start_new_file());
+                    }
+                    line.push_str(&names);
+                    line.push('\n');
+                    self.header = Some(names);
+                }
+                let values: Vec<String> = row.iter().map(|&(_, v)| format!("{}", v)).collect();
+                line.push_str(&values.join(","));
+            },
+            Format::Jsonl => {
+                let fields: Vec<String> = row.iter()
+                    .map(|&(ref n, v)| format!("{}:{}", json::encode(n).unwrap(),
+                                               json::encode(&v).unwrap()))
+                    .collect();
+                line.push('{');
+                line.push_str(&fields.join(","));
+                line.push('}');
+            }
+        }
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+            .and_then(|_| self.file.flush())
+            .map_err(|e| format!("Cannot write metrics: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn row(cycles: f64) -> Row {
+        vec![("cycles".to_string(), cycles), ("mean_return".to_string(), ::std::f64::NAN)]
+    }
+
+    #[test]
+    fn csv_header_is_written_once() {
+        let dir = env::temp_dir().join("polygon-metrics-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        MetricsLog::open(&dir, Format::Csv).unwrap().write(&row(1.0)).unwrap();
+        MetricsLog::open(&dir, Format::Csv).unwrap().write(&row(2.0)).unwrap();
+        MetricsLog::open(&dir, Format::Jsonl).unwrap().write(&row(3.0)).unwrap();
+        let mut csv = String::new();
+        File::open(dir.join("metrics.csv")).unwrap().read_to_string(&mut csv).unwrap();
+        assert_eq!(csv, "cycles,mean_return\n1,NaN\n2,NaN\n");
+        let mut jsonl = String::new();
+        File::open(dir.join("metrics.jsonl")).unwrap().read_to_string(&mut jsonl).unwrap();
+        assert_eq!(jsonl, "{\"cycles\":3.0,\"mean_return\":null}\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn csv_with_other_columns_is_moved_away() {
+        let dir = env::temp_dir().join("polygon-metrics-columns-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        MetricsLog::open(&dir, Format::Csv).unwrap().write(&row(1.0)).unwrap();
+        let mut other = row(2.0);
+        other.push(("td_var".to_string(), 0.5));
+        MetricsLog::open(&dir, Format::Csv).unwrap().write(&other).unwrap();
+        MetricsLog::open(&dir, Format::Csv).unwrap().write(&row(3.0)).unwrap();
+        let read = |name: &str| {
+            let mut csv = String::new();
+            File::open(dir.join(name)).unwrap().read_to_string(&mut csv).unwrap();
+            csv
+        };
+        assert_eq!(read("metrics.1.csv"), "cycles,mean_return\n1,NaN\n");
+        assert_eq!(read("metrics.2.csv"), "cycles,mean_return,td_var\n2,NaN,0.5\n");
+        assert_eq!(read("metrics.csv"), "cycles,mean_return\n3,NaN\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
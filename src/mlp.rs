@@ -0,0 +1,305 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::mem;
+use std::path;
+use rand::{Rng, StdRng};
+use rustc_serialize::json;
+use approx::Approx;
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Activation {
+    Linear,
+    Tanh,
+    Sigmoid,
+    Relu,
+}
+
+impl Activation {
+    pub fn from_name(name: &str) -> Option<Activation> {
+        match name {
+            "linear" => Some(Activation::Linear),
+            "tanh" => Some(Activation::Tanh),
+            "sigmoid" => Some(Activation::Sigmoid),
+            "relu" => Some(Activation::Relu),
+            _ => None
+        }
+    }
+
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Activation::Linear => x,
+            Activation::Tanh => x.tanh(),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Relu => if x > 0.0 { x } else { 0.0 },
+        }
+    }
+
+    /// Derivative expressed through the output `y`
+    fn derivative(self, y: f64) -> f64 {
+        match self {
+            Activation::Linear => 1.0,
+            Activation::Tanh => 1.0 - y * y,
+            Activation::Sigmoid => y * (1.0 - y),
+            Activation::Relu => if y > 0.0 { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Init {
+    /// Uniform in -scale..scale
+    Uniform(f64),
+    /// Uniform, scaled by the number of inputs and outputs of the layer
+    Xavier,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+struct Layer {
+    inputs: usize,
+    outputs: usize,
+    activation: Activation,
+    /// Row per output: the weights of the inputs followed by the bias
+    weights: Vec<f64>,
+}
+
+impl Layer {
+    fn new(inputs: usize, outputs: usize, activation: Activation,
+           init: Init, rng: &mut StdRng) -> Layer {
+        let scale = match init {
+            Init::Uniform(s) => s,
+            Init::Xavier => (6.0 / (inputs + outputs) as f64).sqrt(),
+        };
+        let n = outputs * (inputs + 1);
+        let weights = if scale > 0.0 {
+            (0..n).map(|_| rng.gen_range(-scale, scale)).collect()
+        } else {
+            vec![0.0; n]
+        };
+        Layer {
+            inputs: inputs,
+            outputs: outputs,
+            activation: activation,
+            weights: weights
+        }
+    }
+
+    fn forward(&self, x: &[f64], y: &mut Vec<f64>) {
+        y.clear();
+        for row in self.weights.chunks(self.inputs + 1) {
+            let mut s = row[self.inputs];
+            for i in 0..self.inputs {
+                s += row[i] * x[i];
+            }
+            y.push(self.activation.apply(s));
+        }
+    }
+}
+
+/// Fully connected network trained by stochastic gradient
+/// descent on the squared error
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Mlp {
+    layers: Vec<Layer>,
+    learning_rate: f64,
+}
+
+impl Mlp {
+    /// `sizes` are the numbers of inputs, of the hidden units and of outputs
+    pub fn new(sizes: &[usize], hidden: Activation, output: Activation, init: Init,
+               learning_rate: f64, rng: &mut StdRng) -> Mlp {
+        let n = sizes.len() - 1;
+        let layers = (0..n).map(|i| {
+            let a = if i + 1 == n { output } else { hidden };
+            Layer::new(sizes[i], sizes[i + 1], a, init, rng)
+        }).collect();
+        Mlp {
+            layers: layers,
+            learning_rate: learning_rate
+        }
+    }
+
+    /// Moves the weights by `lr` along the gradient of an objective,
+    /// given as `grad`, its gradient by the outputs for `x`
+    pub fn ascend(&mut self, x: &[f64], grad: &[f64], lr: f64) {
+        let outs = self.forward(x);
+        let (deltas, _) = self.backward(&outs, grad);
+        self.apply(&outs, &deltas, lr);
+    }
+
+    /// Gradient of an objective by the inputs, given
+    /// its gradient `grad` by the outputs for `x`
+    pub fn input_gradient(&self, x: &[f64], grad: &[f64]) -> Vec<f64> {
+        let outs = self.forward(x);
+        self.backward(&outs, grad).1
+    }
+
+    /// Moves the weights by `tau` towards the weights of `source`
+    pub fn soft_update(&mut self, source: &Mlp, tau: f64) {
+        for (l, s) in self.layers.iter_mut().zip(source.layers.iter()) {
+            for (w, sw) in l.weights.iter_mut().zip(s.weights.iter()) {
+                *w += tau * (sw - *w);
+            }
+        }
+    }
+
+    // Outputs of all the layers, preceded by the input
+    fn forward(&self, x: &[f64]) -> Vec<Vec<f64>> {
+        let mut outs = Vec::with_capacity(self.layers.len() + 1);
+        outs.push(x.to_vec());
+        for l in self.layers.iter() {
+            let mut y = Vec::new();
+            l.forward(&outs[outs.len() - 1], &mut y);
+            outs.push(y);
+        }
+        outs
+    }
+
+    // Gradients by the weighted sums of every layer
+    // and by the input, from the gradient by the outputs
+    fn backward(&self, outs: &[Vec<f64>], grad: &[f64]) -> (Vec<Vec<f64>>, Vec<f64>) {
+        let n = self.layers.len();
+        let last = self.layers[n - 1].activation;
+        let mut delta: Vec<f64> = outs[n].iter().zip(grad.iter())
+            .map(|(y, g)| g * last.derivative(*y))
+            .collect();
+        let mut deltas = vec![Vec::new(); n];
+        for k in (0..n).rev() {
+            let layer = &self.layers[k];
+            let stride = layer.inputs + 1;
+            let mut below = Vec::with_capacity(layer.inputs);
+            for i in 0..layer.inputs {
+                let mut s = 0.0;
+                for o in 0..layer.outputs {
+                    s += delta[o] * layer.weights[o * stride + i];
+                }
+                if k > 0 {
+                    s *= self.layers[k - 1].activation.derivative(outs[k][i]);
+                }
+                below.push(s);
+            }
+            deltas[k] = delta;
+            delta = below;
+        }
+        (deltas, delta)
+    }
+
+    fn apply(&mut self, outs: &[Vec<f64>], deltas: &[Vec<f64>], lr: f64) {
+        for (k, layer) in self.layers.iter_mut().enumerate() {
+            let input = &outs[k];
+            let stride = layer.inputs + 1;
+            for (o, row) in layer.weights.chunks_mut(stride).enumerate() {
+                let d = lr * deltas[k][o];
+                for i in 0..layer.inputs {
+                    row[i] += d * input[i];
+                }
+                row[layer.inputs] += d;
+            }
+        }
+    }
+}
+
+impl Approx for Mlp {
+    fn call(&self, x: &[f64]) -> Vec<f64> {
+        let mut a = x.to_vec();
+        let mut b = Vec::new();
+        for l in self.layers.iter() {
+            l.forward(&a, &mut b);
+            mem::swap(&mut a, &mut b);
+        }
+        a
+    }
+
+    fn update(&mut self, target: &[f64], x: &[f64]) {
+        let outs = self.forward(x);
+        let grad: Vec<f64> = outs[outs.len() - 1].iter().zip(target.iter())
+            .map(|(y, t)| t - y)
+            .collect();
+        let (deltas, _) = self.backward(&outs, &grad);
+        let lr = self.learning_rate;
+        self.apply(&outs, &deltas, lr);
+    }
+
+    fn save(&self, filename: &path::Path) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut f| write!(f, "{}", json::encode(self).unwrap()))
+            .map_err(|e| format!("Cannot save {:?}: {}", filename, e))
+    }
+
+    fn load(&mut self, filename: &path::Path) -> Result<(), String> {
+        let mut js = String::new();
+        try!(File::open(filename)
+             .and_then(|mut f| f.read_to_string(&mut js))
+             .map_err(|e| format!("Cannot load {:?}: {}", filename, e)));
+        let mlp: Mlp = try!(json::decode(&js)
+                            .map_err(|e| format!("Bad network {:?}: {}", filename, e)));
+        if mlp.sizes() != self.sizes() {
+            return Err(format!("Network {:?} has layers {:?}, expected {:?}",
+                               filename, mlp.sizes(), self.sizes()));
+        }
+        *self = mlp;
+        Ok(())
+    }
+
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![self.layers[0].inputs];
+        sizes.extend(self.layers.iter().map(|l| l.outputs));
+        sizes
+    }
+
+    fn print(&self) {
+        for (i, l) in self.layers.iter().enumerate() {
+            println!("Layer {} ({:?}): {:?}", i, l.activation, l.weights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::Approx;
+    use rand::{SeedableRng, StdRng};
+
+    #[test]
+    fn mlp_learns_a_function() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[1usize][..]);
+        let mut mlp = Mlp::new(&[2, 8, 1], Activation::Tanh, Activation::Linear,
+                               Init::Uniform(0.5), 0.05, &mut rng);
+        let f = |x: &[f64]| vec![0.5 * x[0] - 0.3 * x[1] * x[1]];
+        let error = |mlp: &Mlp, rng: &mut StdRng| {
+            (0..200).map(|_| {
+                let x = [rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)];
+                (mlp.call(&x)[0] - f(&x)[0]).powi(2)
+            }).sum::<f64>() / 200.0
+        };
+        let before = error(&mlp, &mut rng);
+        for _ in 0..20000 {
+            let x = [rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)];
+            mlp.update(&f(&x), &x);
+        }
+        let after = error(&mlp, &mut rng);
+        assert!(after < 0.01 && after < before / 5.0, "{} -> {}", before, after);
+    }
+
+    #[test]
+    fn input_gradient_matches_finite_differences() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[2usize][..]);
+        let mlp = Mlp::new(&[3, 5, 4, 2], Activation::Tanh, Activation::Sigmoid,
+                           Init::Xavier, 0.1, &mut rng);
+        let x = [0.3, -0.7, 0.2];
+        let grad = [1.0, -2.0];
+        let g = mlp.input_gradient(&x, &grad);
+        let objective = |x: &[f64]| {
+            let y = mlp.call(x);
+            grad[0] * y[0] + grad[1] * y[1]
+        };
+        let h = 1e-6;
+        for i in 0..3 {
+            let mut xp = x.to_vec();
+            let mut xm = x.to_vec();
+            xp[i] += h;
+            xm[i] -= h;
+            let fd = (objective(&xp) - objective(&xm)) / (2.0 * h);
+            assert!((fd - g[i]).abs() < 1e-6, "{}: {} vs {}", i, fd, g[i]);
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use cacla::Range;
+use polygon::{World, RAY_RANGE};
+use std::f64::consts::PI;
+
+/// Provider of a part of the state vector
+pub trait Feature: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn dim(&self) -> usize;
+
+    /// Ranges the values are normalized from. They are wider than
+    /// the values themselves, so normalized values stay well inside -1..1.
+    fn ranges(&self) -> Vec<Range>;
+
+    fn fill(&self, world: &World, out: &mut [f64]);
+}
+
+// Range with a margin of half its width on both sides
+fn widened(lo: f64, hi: f64) -> Range {
+    let m = 0.5 * (hi - lo);
+    Range::new(lo - m, hi + m)
+}
+
+fn clamp(x: f64, lo: f64, hi: f64) -> f64 {
+    if x < lo {
+        lo
+    } else if x > hi {
+        hi
+    } else {
+        x
+    }
+}
+
+/// Limit for the speed, wheels angle, actions and progress values
+const LIMIT: f64 = 5.0;
+
+/// Distances to the walls along the rays, clipped at `clip`
+pub struct Rays {
+    count: usize,
+    clip: f64,
+}
+
+impl Rays {
+    pub fn new(count: usize, clip: f64) -> Rays {
+        Rays {
+            count: count,
+            clip: clip
+        }
+    }
+}
+
+impl Feature for Rays {
+    fn name(&self) -> &str { "rays" }
+
+    fn dim(&self) -> usize { self.count }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![Range::new(-0.5 * self.clip, 2.0 * self.clip); self.count]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        for (i, isx) in world.car.isxs.iter().enumerate() {
+            out[i] = if isx.dist < self.clip { isx.dist } else { self.clip };
+        }
+    }
+}
+
+pub struct Speed;
+
+impl Feature for Speed {
+    fn name(&self) -> &str { "speed" }
+
+    fn dim(&self) -> usize { 1 }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-LIMIT, LIMIT)]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        out[0] = clamp(world.car.speed, -LIMIT, LIMIT);
+    }
+}
+
+pub struct WheelsAngle;
+
+impl Feature for WheelsAngle {
+    fn name(&self) -> &str { "wheels_angle" }
+
+    fn dim(&self) -> usize { 1 }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-LIMIT, LIMIT)]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        out[0] = clamp(world.car.wheels_angle, -LIMIT, LIMIT);
+    }
+}
+
+/// Action taken on the previous step
+pub struct LastAction {
+    count: usize,
+}
+
+impl LastAction {
+    pub fn new(count: usize) -> LastAction {
+        LastAction {
+            count: count
+        }
+    }
+}
+
+impl Feature for LastAction {
+    fn name(&self) -> &str { "last_action" }
+
+    fn dim(&self) -> usize { self.count }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-LIMIT, LIMIT); self.count]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        for i in 0..self.count {
+            out[i] = clamp(world.last_action[i], -LIMIT, LIMIT);
+        }
+    }
+}
+
+/// Distance travelled along the way on the last step
+pub struct WayProgress;
+
+impl Feature for WayProgress {
+    fn name(&self) -> &str { "way_progress" }
+
+    fn dim(&self) -> usize { 1 }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-LIMIT, LIMIT)]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        let offset = world.way.offset(&world.old_way_point, &world.way_point);
+        out[0] = clamp(offset, -LIMIT, LIMIT);
+    }
+}
+
+/// Angle between the course of the car and the direction of the way
+pub struct Heading;
+
+impl Feature for Heading {
+    fn name(&self) -> &str { "heading" }
+
+    fn dim(&self) -> usize { 1 }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-PI, PI)]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        let t = world.way.direction(&world.way_point);
+        let c = world.car.course;
+        out[0] = (t.x * c.y - t.y * c.x).atan2(t.x * c.x + t.y * c.y);
+    }
+}
+
+/// Signed turn of the way within `distance` ahead of the car
+pub struct CurvatureAhead {
+    distance: f64,
+}
+
+impl CurvatureAhead {
+    pub fn new(distance: f64) -> CurvatureAhead {
+        CurvatureAhead {
+            distance: distance
+        }
+    }
+}
+
+impl Feature for CurvatureAhead {
+    fn name(&self) -> &str { "curvature_ahead" }
+
+    fn dim(&self) -> usize { 1 }
+
+    fn ranges(&self) -> Vec<Range> {
+        vec![widened(-PI, PI)]
+    }
+
+    fn fill(&self, world: &World, out: &mut [f64]) {
+        let turn = world.way.turn_ahead(&world.way_point, self.distance);
+        out[0] = clamp(turn, -PI, PI);
+    }
+}
+
+/// Feature as written in configuration. `param` is the clip distance
+/// for "rays" and the look-ahead distance for "curvature_ahead";
+/// `range` overrides the range of all the feature values, and is
+/// widened for normalization as the default ranges are.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct FeatureConfig {
+    pub name: String,
+    pub param: Option<f64>,
+    pub range: Option<[f64; 2]>,
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ObservationConfig {
+    pub features: Vec<FeatureConfig>,
+}
+
+impl ObservationConfig {
+    /// Ray distances only
+    pub fn rays() -> ObservationConfig {
+        ObservationConfig {
+            features: vec![FeatureConfig {
+                name: "rays".to_string(),
+                param: None,
+                range: None
+            }]
+        }
+    }
+}
+
+/// Builds the state vector of a world from the selected features
+pub struct Observer {
+    features: Vec<Box<Feature>>,
+    ranges: Vec<Range>,
+}
+
+impl Observer {
+    pub fn new(features: Vec<Box<Feature>>) -> Observer {
+        let mut ranges = Vec::new();
+        for f in features.iter() {
+            ranges.extend(f.ranges());
+        }
+        Observer {
+            features: features,
+            ranges: ranges
+        }
+    }
+
+    pub fn from_config(config: &ObservationConfig,
+                       nrays: usize, action_dim: usize) -> Result<Observer, String> {
+        let mut features: Vec<Box<Feature>> = Vec::new();
+        let mut overrides = Vec::new();
+        for fc in config.features.iter() {
+            let f: Box<Feature> = match fc.name.as_ref() {
+                "rays" => Box::new(Rays::new(nrays, fc.param.unwrap_or(RAY_RANGE))),
+                "speed" => Box::new(Speed),
+                "wheels_angle" => Box::new(WheelsAngle),
+                "last_action" => Box::new(LastAction::new(action_dim)),
+                "way_progress" => Box::new(WayProgress),
+                "heading" => Box::new(Heading),
+                "curvature_ahead" => Box::new(CurvatureAhead::new(fc.param.unwrap_or(30.0))),
+                name => return Err(format!("Unknown observation feature: {}", name))
+            };
+            overrides.push((f.dim(), fc.range));
+            features.push(f);
+        }
+        let mut observer = Observer::new(features);
+        let mut i = 0;
+        for (k, (dim, range)) in overrides.into_iter().enumerate() {
+            if let Some(r) = range {
+                if !(r[0] < r[1]) {
+                    return Err(format!("Empty range of observation feature {}: {:?}",
+                                       config.features[k].name, r));
+                }
+                for j in i..i+dim {
+                    observer.ranges[j] = widened(r[0], r[1]);
+                }
+            }
+            i += dim;
+        }
+        Ok(observer)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn ranges(&self) -> Vec<Range> {
+        self.ranges.clone()
+    }
+
+    pub fn feature_names(&self) -> Vec<&str> {
+        self.features.iter().map(|f| f.name()).collect()
+    }
+
+    pub fn observe(&self, world: &World, state: &mut [f64]) {
+        let mut i = 0;
+        for f in self.features.iter() {
+            let n = f.dim();
+            f.fill(world, &mut state[i..i+n]);
+            i += n;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn feature(name: &str, range: Option<[f64; 2]>) -> FeatureConfig {
+        FeatureConfig {
+            name: name.to_string(),
+            param: None,
+            range: range
+        }
+    }
+
+    #[test]
+    fn unknown_feature_is_refused() {
+        let config = ObservationConfig { features: vec![feature("altitude", None)] };
+        let err = Observer::from_config(&config, 3, 2).err().unwrap();
+        assert!(err.contains("altitude"), "{}", err);
+    }
+
+    #[test]
+    fn range_override_applies_to_its_feature() {
+        let config = ObservationConfig {
+            features: vec![feature("speed", None), feature("rays", Some([0.0, 8.0])),
+                           feature("heading", None)]
+        };
+        let observer = Observer::from_config(&config, 3, 2).unwrap();
+        let ranges = observer.ranges();
+        assert_eq!(ranges.len(), 5);
+        assert_eq!((ranges[0].lo, ranges[0].hi), (-2.0 * LIMIT, 2.0 * LIMIT));
+        for r in ranges[1..4].iter() {
+            assert_eq!((r.lo, r.hi), (-4.0, 12.0));
+        }
+        assert_eq!((ranges[4].lo, ranges[4].hi), (-2.0 * PI, 2.0 * PI));
+    }
+
+    #[test]
+    fn empty_range_is_refused() {
+        for &r in [[1.0, 1.0], [2.0, 1.0]].iter() {
+            let config = ObservationConfig { features: vec![feature("speed", Some(r))] };
+            assert!(Observer::from_config(&config, 3, 2).is_err());
+        }
+    }
+}
@@ -1,15 +1,39 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::{Arc, mpsc};
+use std::thread;
 use car::Car;
-use geom::{Figure, Pt};
-use track::{clover, Way, WayPoint, clover_data};
-use cacla::{Cacla, Range};
-use std::f64::consts::PI;
+use geom::{Figure, Grid, Pt};
+use track::{TrackData, Way, WayPoint};
+use cacla::Range;
+use learner::{self, Learner};
 use std::path;
-use rand::{thread_rng, Rng};
+use std::fs;
+use rand::{SeedableRng, StdRng};
+use seed;
+use config::{Config, CarConfig, TrackConfig, CONFIG_FILE};
+use checkpoint::{self, Manifest, Progress, read_json, write_json};
+use env::Done;
+use episode::{EpisodeConfig, EpisodeState, EpisodeStats, Episode};
+use reward::{RewardFn, WeightedReward};
+use observation::Observer;
+use eval::{EvalOptions, EvalEpisode, EvalReport};
+use metrics::{Interval, Row};
+use recording::Recording;
+use trackgen::{self, TrackGenConfig};
+use trackcheck;
+use laps::LapTracker;
+use std::mem;
 
 const TRANGE: Range = Range{lo: -1.0, hi: 1.0};
 
+/// Ray distances are clipped to this value
+pub const RAY_RANGE: f64 = 10.0;
+
+/// How far along the way the way point of a car is looked for from
+/// the one of the step before, at least; far more than a car drives
+/// in a step at usual speeds. Faster cars look twice as far as they
+/// drove, as the speed is not limited.
+pub const WAY_REACH: f64 = 20.0;
+
 pub struct MinMax {
     ranges: Vec<Range>,
 }
@@ -20,10 +44,11 @@ impl MinMax {
             ranges: ranges.clone()
         }
     }
+    // Values outside of their ranges are clipped
     fn norm(&self, inp: &Vec<f64>, out: &mut Vec<f64>) {
         let n = inp.len();
         for i in 0..n {
-            out[i] = normalize(&self.ranges[i], inp[i], &TRANGE);
+            out[i] = normalize(&self.ranges[i], inp[i], &TRANGE).max(TRANGE.lo).min(TRANGE.hi);
         }
         //println!("rng={:?}", self.ranges);
     }
@@ -43,39 +68,65 @@ fn normalize(from: &Range, x: f64, to: &Range) -> f64 {
 
 pub struct World {
     pub car: Car,
-    pub walls: Rc<Figure>,
-    pub way: Rc<Way>,
+    pub walls: Arc<Figure>,
+    pub walls_grid: Arc<Grid>,
+    pub way: Arc<Way>,
     pub way_point: WayPoint,
     pub old_way_point: WayPoint,
     pub state: Vec<f64>,
     //pub prev_state: Vec<f64>,
-    pub last_action: Vec<f64>
+    pub observer: Arc<Observer>,
+    pub last_action: Vec<f64>,
+    pub reward_fn: Arc<RewardFn>,
+    pub reward_terms: Vec<f64>,
+    pub last_reward: f64,
+    pub episode_config: Arc<EpisodeConfig>,
+    pub episode: EpisodeState,
+    /// Laps and checkpoints of the episode
+    pub laps: LapTracker,
+    pub rng: StdRng,
 }
 
 impl World {
-    pub fn new(nrays: usize, walls: Rc<Figure>,
-           way: Rc<Way>,
-           state_dim: usize, action_dim: usize) -> World {
-        let mut state = Vec::with_capacity(state_dim);
-        state.resize(state_dim, 0.0);
+    pub fn new(car: &CarConfig, nrays: usize, walls: Arc<Figure>,
+           walls_grid: Arc<Grid>,
+           way: Arc<Way>,
+           observer: Arc<Observer>,
+           reward_fn: Arc<RewardFn>,
+           episode_config: Arc<EpisodeConfig>,
+           action_dim: usize) -> World {
+        let mut state = Vec::with_capacity(observer.dim());
+        state.resize(observer.dim(), 0.0);
         let mut last_action = Vec::with_capacity(action_dim);
         last_action.resize(action_dim, 0.0);
-        let car = Car::new(Pt::new(-110.0, 0.0),
-                            Pt::new(0.0, 1.0),
-                            3.0, // length
-                            1.6, // width
+        let mut rng: StdRng = SeedableRng::from_seed(&[0usize][..]);
+        let (center, course) = episode_config.spawn(&way, &mut rng);
+        let car = Car::new(center,
+                            course,
+                            car.length,
+                            car.width,
                             nrays,
-                            walls.clone());
-        let center = car.center;
+                            walls_grid.clone());
+        let nterms = reward_fn.term_names().len();
+        let laps = episode_config.lap_tracker(&way, center);
         World {
             car: car,
             walls: walls,
+            walls_grid: walls_grid,
             way: way.clone(),
             way_point: way.where_is(center),
             old_way_point: WayPoint::zero(),
             state: state,
             //prev_state: state.clone(),
-            last_action: last_action
+            observer: observer,
+            last_action: last_action,
+            reward_fn: reward_fn,
+            reward_terms: vec![0.0; nterms],
+            last_reward: 0.0,
+            episode_config: episode_config,
+            episode: EpisodeState::new(),
+            laps: laps,
+            rng: rng,
         }
     }
 
@@ -83,224 +134,321 @@ impl World {
         World {
             car: self.car.clone(),
             walls: self.walls.clone(),
+            walls_grid: self.walls_grid.clone(),
             way: self.way.clone(),
             way_point: self.way_point,
             old_way_point: self.old_way_point,
             state: self.state.clone(),
-            last_action: self.last_action.clone()
+            observer: self.observer.clone(),
+            last_action: self.last_action.clone(),
+            reward_fn: self.reward_fn.clone(),
+            reward_terms: self.reward_terms.clone(),
+            last_reward: self.last_reward,
+            episode_config: self.episode_config.clone(),
+            episode: self.episode,
+            laps: self.laps.clone(),
+            rng: self.rng.clone(),
         }
     }
 
-    pub fn act(&mut self, action: &Vec<f64>) {
+    pub fn act(&mut self, action: &[f64]) {
+        let was_hit = self.car.hit;
+        let center = self.car.center;
         self.car.act(action);
         self.old_way_point = self.way_point;
-        self.way_point = self.way.where_is(self.car.center);
-        self.recalc_state();
+        let reach = WAY_REACH.max(2.0 * (self.car.center - center).norm());
+        self.way_point = self.way.where_is_near(self.car.center, &self.old_way_point, reach);
+        let offset = self.way.offset(&self.old_way_point, &self.way_point);
+        self.laps.step(center, self.car.center, offset);
         self.last_action.clone_from_slice(action);
+        self.recalc_state();
+        self.recalc_reward();
+        self.episode.update(self.last_reward, offset, self.car.speed);
+        if self.car.hit && !was_hit {
+            self.episode.collisions += 1;
+        }
     }
 
-    pub fn reward_old_2(&self) -> f64 {
-        let mut hp: f64 = 0.0;
-        if self.car.speed.abs() < 0.001 {
-            hp = 1.0;
-        }
-        let ap = 2.0 * self.car.wheels_angle.abs();
-        let cap = self.car.action_penalty(&self.last_action);
-        let penalty = ap + hp + cap;
-        let speed = self.car.speed;
-        if speed > 0.0 {
-            speed - penalty
-        } else {
-            -0.5 * speed - penalty
-        }
+    /// Starts a new episode from a spawn pose
+    pub fn reset(&mut self) {
+        let (center, course) = self.episode_config.spawn(&self.way, &mut self.rng);
+        self.reset_to(center, course);
     }
 
-    pub fn reward_old(&self) -> f64 {
-        /*
-        let mut hit_penalty = 0.0;
-        if self.car.borrow().speed.abs() < 0.0000001 {
-            hit_penalty = 0.1;
+    /// Starts a new episode from the given pose
+    pub fn reset_to(&mut self, center: Pt, course: Pt) {
+        self.car.reset(center, course);
+        self.way_point = self.way.where_is(center);
+        self.old_way_point = self.way_point;
+        self.episode = EpisodeState::new();
+        self.laps = self.episode_config.lap_tracker(&self.way, center);
+        for a in self.last_action.iter_mut() {
+            *a = 0.0;
         }
-
-        TODO: New continuous hit penalty
-        */
-        let mut min_dist = 1.0e20;
-        for i in 0..self.state.len() - 2 {
-            if (self.state[i] >= 0.0) && (self.state[i] < min_dist) {
-                min_dist = self.state[i];
-            }
+        for t in self.reward_terms.iter_mut() {
+            *t = 0.0;
         }
+        self.last_reward = 0.0;
+        self.recalc_state();
+    }
 
-        //let hit_penalty = if min_dist < 1.0 { 1.0 - min_dist } else { 0.0 };
-
-        let sigma = 2.0 / 3.0;
-        let hit_penalty = (-min_dist*min_dist / (2.0 * sigma * sigma)).exp();
-
-        let ap = self.car.wheels_angle.abs(); // 0.0..2.0
-        let cap = self.car.action_penalty(&self.last_action); // 0.0..2.0
-        let cap2 = self.car.action_penalty2(&self.last_action); // 0.0..1.0
-        //let penalty = hit_penalty + cap + ap;
+    /// Reason to finish the episode, if there is one
+    pub fn done(&self) -> Option<Done> {
+        self.episode_config.done(&self.episode, &self.laps, self.car.hit)
+    }
 
-        let penalty = 1.0 * (1.0 * hit_penalty + 1.0 * cap + 1.0 * ap + 2.0 * cap2);
-        //let reward = self.way.offset(&self.old_way_point, &self.way_point); // -1.0..1.0
-        //5.0 * reward - penalty
+    /// Reward for the last step
+    pub fn reward(&self) -> f64 {
+        self.last_reward
+    }
 
-        let speed = self.car.speed;
-        if speed > 0.0 {
-            speed - penalty
-        } else {
-            -0.5 * speed - penalty
-        }
+    fn recalc_reward(&mut self) {
+        let reward_fn = self.reward_fn.clone();
+        let mut terms = mem::replace(&mut self.reward_terms, Vec::new());
+        self.last_reward = reward_fn.reward(self, &mut terms);
+        self.reward_terms = terms;
+    }
 
+    fn recalc_state(&mut self) {
+        let observer = self.observer.clone();
+        let mut state = mem::replace(&mut self.state, Vec::new());
+        observer.observe(self, &mut state);
+        self.state = state;
     }
+}
 
-    pub fn reward(&self) -> f64 {
-        let speed = self.car.speed;
-        let mut dist_reward = 0.0;
-        let speed_reward = 1.0 - (speed - 1.0) * (speed - 1.0);
-
-        //println!("--reward");
-        //println!("center: {:?}", self.car.center);
-        //println!("course: {:?}", self.car.course);
-        //println!("speed: {:?}", self.car.speed);
-        //println!("wheels: {:?}", self.car.wheels_angle);
-        //println!("action: {:?}", self.last_action);
-        let offset = self.way.offset(&self.old_way_point, &self.way_point);
-        let offset_reward = 300.0 * offset;
-        /*let mut speed_reward = speed;
-        if speed < 0.0 {
-            speed_reward /= 2.0;
-        }*/
-
-        for i in 0..self.nrays() {
-            let s = self.state[i];
-            dist_reward = min(s * (1.0 - 0.0099 * s), dist_reward);
-        }
+/// Directory of the workspace, created if it does not exist
+pub fn dir_of_workspace(workspace: &str) -> Result<path::PathBuf, String> {
+    let dir = workspace_path(workspace);
+    try!(fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {:?}: {}", dir, e)));
+    Ok(dir)
+}
 
-        let wheels = self.car.wheels_angle;
-        let wheels_reward = - wheels * wheels;
+/// Directory of the workspace, which may not exist
+pub fn workspace_path(workspace: &str) -> path::PathBuf {
+    path::Path::new("./workspaces/").join(workspace)
+}
 
-        let action_penalty = self.car.action_penalty3(&self.last_action);
-        let action_reward = -action_penalty * action_penalty;
-        let speed_penalty = -speed*speed;
-        let mut speed_reward = speed;
-        if speed < 0.0 {
-            speed_reward = -speed/2.0;
-        }
+/// Whether the worlds get tracks of their own
+pub fn track_per_world(tc: &TrackConfig) -> bool {
+    tc.file.is_none() && tc.name == "generated"
+        && tc.generator.as_ref().map_or(false, |g| g.per_world)
+}
 
-        /*offset_reward +*/ 10.0*speed_reward + /*speed_reward +*/ 20.0*dist_reward + 5.0*wheels_reward + action_reward + 10.0 * speed_penalty
+/// Center line, walls and spawn poses of the configured track.
+/// A generated track comes from the experiment seed, and
+/// from the index of the world if it has one of its own.
+/// Track files and generated tracks have to fit a car of the given width.
+pub fn track_data(tc: &TrackConfig, seed: u64, world: usize, car_width: f64)
+                  -> Result<TrackData, String> {
+    if let Some(ref file) = tc.file {
+        let data = try!(TrackData::load(path::Path::new(file)));
+        try!(trackcheck::check(&data, car_width).map_err(|e| format!("{}: {}", file, e)));
+        return Ok(data);
     }
-
-    fn recalc_state(&mut self) {
-        //self.prev_state.clone_from(&self.state);
-        let n = self.nrays();
-        for (i, isx) in self.car.isxs.iter().enumerate() {
-            self.state[i] = if isx.dist < 10.0 { isx.dist } else { 10.0 }; // 10.0; // !!!
-        }
-        //self.state[n] = self.car.speed; // / 1.0; // !!!
-        //self.state[n+1] = self.car.wheels_angle; // / 1.0; // !!!
-        //self.state[n+2] = self.car.action_penalty3(&self.last_action);
-        
-        //println!("--recalc_state");
-        //println!("center: {:?}", self.car.center);
-        //println!("course: {:?}", self.car.course);
-        //println!("speed: {:?}", self.car.speed);
-        //println!("wheels: {:?}", self.car.wheels_angle);
-        //println!("action: {:?}", self.last_action);
-        
-        //self.state[n+3] = self.way.offset(&self.old_way_point, &self.way_point);
-    }
-
-    fn nrays(&self) -> usize {
-        self.car.isxs.len()
+    match tc.name.as_ref() {
+        "clover" => Ok(TrackData::clover(tc.half_width, tc.scale)),
+        "generated" => {
+            let gc = tc.generator.clone().unwrap_or_else(TrackGenConfig::default);
+            let index = if gc.per_world { world as u64 } else { 0 };
+            trackgen::generate(&gc, tc.half_width, car_width,
+                               &mut seed::rng(seed, seed::TRACKS + index))
+        },
+        name => Err(format!("Unknown track: {}", name))
     }
 }
 
-fn min(a: f64, b: f64) -> f64 {
-    if a > b {
-        b
-    } else {
-        a
+/// Walls of the configured track, their grid and the way along it.
+/// The spawn poses of a track file go into `episode`.
+pub fn build_track(tc: &TrackConfig, seed: u64, world: usize, car_width: f64,
+                   episode: &mut EpisodeConfig)
+                   -> Result<(Arc<Figure>, Arc<Grid>, Arc<Way>), String> {
+    let data = try!(track_data(tc, seed, world, car_width));
+    let spawns = data.spawn_poses();
+    if !spawns.is_empty() {
+        episode.spawn.poses = spawns;
     }
+    let walls = Arc::new(data.walls());
+    let walls_grid = Arc::new(Grid::new(&walls, tc.grid_cell));
+    let way = Arc::new(data.way());
+    Ok((walls, walls_grid, way))
 }
 
 pub struct Polygon {
     pub worlds: Vec<World>,
-    pub walls: Rc<Figure>,
+    /// Walls of world 0; with tracks per world the others have their own
+    pub walls: Arc<Figure>,
     pub last_reward: f64,
-    pub learner: Cacla,
+    pub learner: Box<Learner>,
+    pub reward_fn: Arc<RewardFn>,
+    pub episodes: EpisodeStats,
+    /// Totals since the last metrics row
+    pub interval: Interval,
     minmax: MinMax,
     reward_range: Range,
-    stopped_cycles: u32,
-    wander_cycles: u32,
-    epoch: u32,
     ws_dir: path::PathBuf,
     current_index: usize,
+    threads: usize,
+    pub config: Config,
+    pub progress: Progress,
 }
 
 impl Polygon {
-    pub fn new(ws_dir: path::PathBuf) -> Polygon {
-        let nrays = 36;
-        let scale = 10.0;
-        let walls = Rc::new(clover(4.0, scale));
-        let way = Rc::new(Way::new(&clover_data, scale));
+    /// Default experiment; all its randomness comes from `seed`
+    pub fn new(ws_dir: path::PathBuf, seed: u64) -> Polygon {
+        let mut config = Config::default();
+        config.seed = seed;
+        Polygon::from_config(ws_dir, &config).unwrap()
+    }
+
+    /// Sets up the experiment and copies its configuration
+    /// to the workspace directory
+    pub fn from_config(ws_dir: path::PathBuf, config: &Config) -> Result<Polygon, String> {
+        let pg = try!(Polygon::with_config(ws_dir.clone(), config));
+        try!(config.save(&ws_dir.join(CONFIG_FILE))
+             .map_err(|e| format!("Cannot save config to {:?}: {}", ws_dir, e)));
+        Ok(pg)
+    }
+
+    /// Sets up the experiment without writing to the workspace,
+    /// as for evaluating a checkpoint
+    pub fn with_config(ws_dir: path::PathBuf, config: &Config) -> Result<Polygon, String> {
+        if let Some(ref laps) = config.episode.laps {
+            try!(laps.check());
+        }
+        let reward_fn: Arc<RewardFn> = Arc::new(try!(WeightedReward::from_config(&config.reward)));
         let action_dim = 2;
-        let state_dim = nrays; //+ 4; // speed + angle + action_penalty + offset
-        let world = World::new(nrays,
-                                walls.clone(),
-                                way.clone(),
-                                state_dim,
-                                action_dim);
-        let state_ranges = Polygon::mk_state_ranges(state_dim);
+        let nrays = config.sensors.nrays;
+        let observer = Arc::new(try!(Observer::from_config(&config.observation,
+                                                          nrays, action_dim)));
+        let new_world = |i: usize| -> Result<World, String> {
+            let mut episode_config = config.episode.clone();
+            let (walls, walls_grid, way) = try!(build_track(&config.track, config.seed, i,
+                                                            config.car.width,
+                                                            &mut episode_config));
+            Ok(World::new(&config.car,
+                          nrays,
+                          walls,
+                          walls_grid,
+                          way,
+                          observer.clone(),
+                          reward_fn.clone(),
+                          Arc::new(episode_config),
+                          action_dim))
+        };
+        let world = try!(new_world(0));
+        let per_world = track_per_world(&config.track);
+        let state_ranges = observer.ranges();
         let minmax = MinMax::new(&state_ranges);
-        let learner = Cacla::new(&state_ranges,
-                            action_dim as u32,
-                            18,   // hidden
-                            0.99,  // gamma
-                            0.1, // alpha !!!
-                            0.001, // beta
-                            0.1);  // sigma
-        let mut worlds = Vec::with_capacity(20);
-        for i in 0..worlds.capacity() {
-            let mut w = world.clone();
+        let learner = try!(learner::build(&config.learner, &state_ranges,
+                                          action_dim, config.worlds, config.seed));
+        let mut worlds = Vec::with_capacity(config.worlds);
+        for i in 0..config.worlds {
+            let mut w = if i > 0 && per_world { try!(new_world(i)) } else { world.clone() };
             //let angle = PI/4.0 * (i as f64 / worlds.capacity() as f64);
             //w.car.course = Pt::new(angle.cos(), angle.sin());
+            w.rng = seed::rng(config.seed, seed::WORLDS + i as u64);
+            w.reset();
             worlds.push(w);
         }
 
-        Polygon {
+        Ok(Polygon {
             worlds: worlds,
-            walls: walls.clone(),
+            walls: world.walls.clone(),
             learner: learner,
+            reward_range: reward_fn.range(),
+            interval: Interval::new(reward_fn.term_names().len()),
+            reward_fn: reward_fn,
+            episodes: EpisodeStats::new(1000),
             minmax: minmax,
-            reward_range: Range::new(-100.0, 100.0), //(-4.0, 1.0) - for reward_old,
             last_reward: 0.0,
-            stopped_cycles: 0,
-            wander_cycles: 0,
-            epoch: 1000000,
             ws_dir: ws_dir,
             current_index: 0,
+            threads: if config.threads > 0 { config.threads } else { 1 },
+            config: config.clone(),
+            progress: Progress::new(),
+        })
+    }
+
+    /// Saves the learner into the workspace directory
+    pub fn save(&self) -> Result<(), String> {
+        let dir = self.ws_dir.clone();
+        self.save_checkpoint(&dir)
+    }
+
+    /// Loads the learner from the workspace directory
+    pub fn load(&mut self) -> Result<(), String> {
+        let dir = self.ws_dir.clone();
+        self.load_checkpoint(&dir)
+    }
+
+    fn manifest(&self) -> Manifest {
+        Manifest {
+            version: checkpoint::VERSION,
+            state_dim: self.worlds[0].state.len(),
+            action_dim: self.worlds[0].last_action.len(),
+            ranges: self.worlds[0].observer.ranges().iter().map(|r| [r.lo, r.hi]).collect(),
+            networks: self.learner.networks(),
+            config: self.config.clone(),
+        }
+    }
+
+    pub fn save_checkpoint(&self, dir: &path::Path) -> Result<(), String> {
+        try!(fs::create_dir_all(dir).map_err(|e| format!("Cannot create {:?}: {}", dir, e)));
+        let manifest_file = dir.join(checkpoint::MANIFEST_FILE);
+        if manifest_file.exists() {
+            try!(fs::remove_file(&manifest_file)
+                 .map_err(|e| format!("Cannot remove {:?}: {}", manifest_file, e)));
         }
+        try!(self.learner.save(dir));
+        let mut progress = self.progress.clone();
+        progress.episodes = self.episodes.count;
+        try!(write_json(&dir.join(checkpoint::PROGRESS_FILE), &progress));
+        // The manifest goes last, so a checkpoint interrupted
+        // while saving has none and is not loaded
+        write_json(&manifest_file, &self.manifest())
     }
 
-    pub fn save(&self) {
-        self.learner.save(&self.ws_dir);
+    /// Loads the learner from a checkpoint made with the same
+    /// learner, state and action dimensions and normalization
+    pub fn load_checkpoint(&mut self, dir: &path::Path) -> Result<(), String> {
+        let manifest: Manifest = try!(read_json(&dir.join(checkpoint::MANIFEST_FILE)));
+        try!(manifest.check(&self.manifest()));
+        let progress_file = dir.join(checkpoint::PROGRESS_FILE);
+        let progress = if progress_file.exists() {
+            try!(read_json(&progress_file))
+        } else {
+            Progress::new()
+        };
+        try!(self.learner.load(dir));
+        self.episodes.count = progress.episodes;
+        self.progress = progress;
+        Ok(())
     }
 
-    pub fn load(&mut self) {
-        self.learner.load(&self.ws_dir);
+    /// Sets the number of threads to step the worlds with. With more
+    /// than one thread all the worlds get their actions from the same
+    /// learner state, then move in parallel, then the learner is
+    /// updated with their transitions in the order of the worlds.
+    /// With one thread each world acts and is learned from in turn.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = if threads > 0 { threads } else { 1 };
     }
 
     pub fn run(&mut self, ncycles: u32) -> f64 {
+        if self.threads > 1 {
+            return self.run_parallel(ncycles);
+        }
         let mut s = self.worlds[0].state.clone();
         let mut new_s = self.worlds[0].state.clone();
-        let N = self.worlds.capacity();
+        let N = self.worlds.len();
         let mut sum_reward = 0.0;
         for _ in 0..ncycles {
             sum_reward += self.run_once_for_world(0, &mut s, &mut new_s);
             for i in 1..N {
                 self.run_once_for_world(i, &mut s, &mut new_s);
             }
+            self.progress.cycles += 1;
         }
         /*
         let M = 20;
@@ -336,31 +484,197 @@ impl Polygon {
         sum_reward
     }
 
+    // The worlds are stepped by workers started once for the run. Each
+    // cycle every worker gets its chunk of the worlds with their
+    // actions and sends the chunk back after the step.
+    fn run_parallel(&mut self, ncycles: u32) -> f64 {
+        let n = self.worlds.len();
+        let chunk = (n + self.threads - 1) / self.threads;
+        let nworkers = (n + chunk - 1) / chunk;
+        let mut ss = vec![self.worlds[0].state.clone(); n];
+        let mut new_s = self.worlds[0].state.clone();
+        let mut sum_reward = 0.0;
+        thread::scope(|scope| {
+            let mut workers = Vec::with_capacity(nworkers);
+            for _ in 0..nworkers {
+                let (tx, rx) = mpsc::channel::<(Vec<World>, Vec<Vec<f64>>)>();
+                let (back_tx, back_rx) = mpsc::channel();
+                scope.spawn(move || {
+                    for (mut ws, actions) in rx {
+                        for (w, a) in ws.iter_mut().zip(actions.iter()) {
+                            w.act(a);
+                        }
+                        if back_tx.send(ws).is_err() {
+                            break;
+                        }
+                    }
+                });
+                workers.push((tx, back_rx));
+            }
+            let mut actions = Vec::with_capacity(n);
+            for _ in 0..ncycles {
+                actions.clear();
+                for i in 0..n {
+                    self.minmax.norm(&self.worlds[i].state, &mut ss[i]);
+                    actions.push(self.learner.get_action(i, &ss[i], false));
+                }
+                let mut worlds = mem::replace(&mut self.worlds, Vec::with_capacity(n)).into_iter();
+                for (k, &(ref tx, _)) in workers.iter().enumerate() {
+                    let ws: Vec<World> = worlds.by_ref().take(chunk).collect();
+                    let acts = actions[k * chunk..k * chunk + ws.len()].to_vec();
+                    tx.send((ws, acts)).expect("World worker has stopped");
+                }
+                for &(_, ref back_rx) in workers.iter() {
+                    let ws = back_rx.recv().expect("World worker has failed");
+                    self.worlds.extend(ws);
+                }
+                for i in 0..n {
+                    let r = self.learn_from_world(i, &ss[i], &mut new_s, &actions[i]);
+                    if i == 0 {
+                        sum_reward += r;
+                    }
+                }
+                self.progress.cycles += 1;
+            }
+        });
+        sum_reward
+    }
+
     pub fn run_once_for_world(&mut self, index: usize, s: &mut Vec<f64>, new_s: &mut Vec<f64>) -> f64 {
         self.minmax.norm(&self.worlds[index].state, s);
-        let a = self.learner.get_action(s, false);
+        let a = self.learner.get_action(index, s, false);
         self.worlds[index].act(&a);
         //println!("state(0): {:?}\n-----------------------------------", &self.worlds[index].state);
+        self.learn_from_world(index, s, new_s, &a)
+    }
+
+    // Updates the learner with the last transition of the world
+    // and starts a new episode for it if the current one is over
+    fn learn_from_world(&mut self, index: usize, s: &Vec<f64>, new_s: &mut Vec<f64>,
+                        a: &Vec<f64>) -> f64 {
         let r = self.worlds[index].reward();
 
         self.minmax.norm(&self.worlds[index].state, new_s);
-        let mut i = 0;
-        let new_s_ = new_s.clone();
-        for x in new_s.into_iter() {
-            if *x > 0.9 || *x < -0.9 {
-                println!("x[{}]={}", i, x);
-                println!("new_s: {:?}", &new_s_);
-                println!("state: {:?}", &self.worlds[index].state);
-                panic!("Normalized out of range");
-            }
-            i += 1;
-        }
-        self.learner.step(s, new_s, &a,
-                            normalize(&self.reward_range, r, &TRANGE));
+        let done = self.worlds[index].done();
+        let terminal = match done {
+            Some(Done::Timeout) | None => false,
+            Some(_) => true,
+        };
+        self.learner.step(index, s, new_s, a,
+                          normalize(&self.reward_range, r, &TRANGE),
+                          terminal);
         self.last_reward = r;
+        self.interval.step(r, &self.worlds[index].reward_terms);
+        if let Some(d) = done {
+            let w = &mut self.worlds[index];
+            let episode = Episode {
+                world: index,
+                ret: w.episode.ret,
+                length: w.episode.steps,
+                progress: w.episode.progress,
+                collisions: w.episode.collisions,
+                done: d,
+            };
+            self.interval.episode(&episode);
+            self.episodes.push(episode);
+            w.reset();
+            self.learner.episode_end(index);
+        }
         r
     }
 
+    /// Runs the greedy policy in a world of its own, leaving the
+    /// learner and the training worlds as they are
+    pub fn evaluate(&self, opts: &EvalOptions) -> Result<EvalReport, String> {
+        let mut config = self.config.clone();
+        if let Some(ref track) = opts.track {
+            config.track = track.clone();
+        }
+        let mut episode_config = config.episode.clone();
+        // Worlds with tracks of their own are evaluated on that of world 0
+        let (walls, walls_grid, way) = try!(build_track(&config.track, config.seed, 0,
+                                                        config.car.width,
+                                                        &mut episode_config));
+        if let Some(ref spawn) = opts.spawn {
+            episode_config.spawn = spawn.clone();
+        }
+        if opts.max_steps.is_some() {
+            episode_config.termination.max_steps = opts.max_steps;
+        }
+        if episode_config.termination.max_steps.is_none() {
+            return Err("Evaluation needs a step limit".to_string());
+        }
+        let mut world = World::new(&self.config.car,
+                                   self.config.sensors.nrays,
+                                   walls,
+                                   walls_grid,
+                                   way.clone(),
+                                   self.worlds[0].observer.clone(),
+                                   self.reward_fn.clone(),
+                                   Arc::new(episode_config),
+                                   self.worlds[0].last_action.len());
+        world.rng = seed::rng(opts.seed, seed::EVAL);
+        let policy = self.learner.ac_fn();
+        let mut s = world.state.clone();
+        let mut episodes = Vec::with_capacity(opts.episodes);
+        if let Some(ref dir) = opts.record {
+            try!(fs::create_dir_all(dir).map_err(|e| format!("Cannot create {:?}: {}", dir, e)));
+        }
+        for i in 0..opts.episodes {
+            world.reset();
+            let mut recording = opts.record.as_ref().map(|_| Recording::new(&config, 0, &world));
+            let mut lap_times = Vec::new();
+            let mut distance = 0.0;
+            let done = loop {
+                self.minmax.norm(&world.state, &mut s);
+                let center = world.car.center;
+                world.act(&policy(&s));
+                distance += (world.car.center - center).norm();
+                if let Some(ref mut r) = recording {
+                    r.record(&world);
+                }
+                if let Some(time) = world.laps.last_lap {
+                    if world.laps.laps as usize > lap_times.len() {
+                        lap_times.push(time);
+                    }
+                }
+                if let Some(d) = world.done() {
+                    break d;
+                }
+            };
+            if let (Some(dir), Some(mut r)) = (opts.record.as_ref(), recording) {
+                r.done = Some(format!("{:?}", done));
+                try!(r.save(&dir.join(format!("episode-{:04}.json", i))));
+            }
+            episodes.push(EvalEpisode {
+                ret: world.episode.ret,
+                steps: world.episode.steps,
+                lap_times: lap_times,
+                collisions: world.episode.collisions,
+                progress: world.episode.progress,
+                distance: distance,
+                done: format!("{:?}", done),
+            });
+        }
+        Ok(EvalReport::new(episodes))
+    }
+
+    /// Metrics of the steps since the last call: cycles, means
+    /// of the reward and its terms, episode returns, collisions,
+    /// progress, and the exploration and the statistics of the learner
+    pub fn metrics(&mut self) -> Row {
+        let mut row = vec![("cycles".to_string(), self.progress.cycles as f64)];
+        row.extend(self.interval.row(&self.reward_fn.term_names()));
+        row.push(("sigma".to_string(), self.learner.sigma()));
+        row.extend(self.learner.metrics());
+        self.interval = Interval::new(self.interval.terms.len());
+        row
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
     pub fn current_world(&self) -> &World {
         &self.worlds[self.current_index]
     }
@@ -394,17 +708,208 @@ impl Polygon {
             y[m as usize]
         })
     }
+}
+
+/// Nominal ranges of the car controls: speed and wheels angle
+/// (in units of PI/4). Actions outside of them are not clipped.
+pub fn action_ranges() -> Vec<Range> {
+    vec![Range::new(-1.0, 1.0), Range::new(-1.0, 1.0)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{World, Polygon, WAY_REACH};
+    use config::{Config, CONFIG_FILE};
+    use eval::EvalOptions;
+    use episode::SpawnConfig;
+    use recording::Recording;
+    use trackgen::TrackGenConfig;
+    use track::TrackData;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    // Workspace of a test, removed when the test ends
+    struct Workspace {
+        dir: PathBuf,
+    }
 
-    fn mk_state_ranges(state_dim: usize) -> Vec<Range> {
-        let mut state_ranges = Vec::new();
-        state_ranges.resize(state_dim, Range::zero());
-        for i in 0..state_dim {
-            state_ranges[i] = Range::new(-5.0, 20.0);
+    impl Workspace {
+        fn new(name: &str) -> Workspace {
+            let dir = env::temp_dir().join(format!("polygon-{}-test-{}", name, process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Workspace { dir: dir }
         }
-        //state_ranges[state_dim-1] = Range::new(-10.0, 10.0); //Range::new(-1.0, 1.0);       // speed
-        //state_ranges[state_dim-1] = Range::new(-10.0, 10.0); //Range::new(-PI/4.0, PI/4.0); // angle
-        //state_ranges[state_dim-1] = Range::new(-10.0, 100.0); //Range::new(0.0, 100.0);     // action penalty
-        //state_ranges[state_dim-1] = Range::new(-10.0, 10.0); //Range::new(-2.0, 2.0);       // offset
-        state_ranges
+
+        fn polygon(&self, config: &Config) -> Result<Polygon, String> {
+            Polygon::from_config(self.dir.clone(), config)
+        }
+    }
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // Small configuration with MLP networks
+    fn config(worlds: usize) -> Config {
+        let mut config = Config::default();
+        config.worlds = worlds;
+        config.learner.approx.backend = "mlp".to_string();
+        config
+    }
+
+    fn is_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn world_is_send_and_sync() {
+        is_send_sync::<World>();
+    }
+
+    #[test]
+    fn checkpoint_refuses_other_state_dimension() {
+        let ws = Workspace::new("checkpoint");
+        let mut config = config(1);
+        ws.polygon(&config).unwrap().save().unwrap();
+        assert!(ws.polygon(&config).unwrap().load().is_ok());
+
+        config.sensors.nrays = 12;
+        let err = ws.polygon(&config).unwrap().load().unwrap_err();
+        assert!(err.contains("state dimension"), "{}", err);
+    }
+
+    #[test]
+    fn with_config_leaves_the_workspace_alone() {
+        let ws = Workspace::new("with-config");
+        let config = config(1);
+        Polygon::with_config(ws.dir.clone(), &config).unwrap();
+        assert!(!ws.dir.join(CONFIG_FILE).exists());
+        ws.polygon(&config).unwrap();
+        assert!(ws.dir.join(CONFIG_FILE).exists());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parallel_run_steps_every_world() {
+        let ws = Workspace::new("parallel");
+        let mut pg = ws.polygon(&config(5)).unwrap();
+        pg.set_threads(2);
+        pg.run(30);
+        assert_eq!(pg.progress.cycles, 30);
+        assert_eq!(pg.worlds.len(), 5);
+        for w in pg.worlds.iter() {
+            assert!(w.episode.steps > 0 || pg.episodes.count > 0);
+        }
+    }
+
+    #[test]
+    fn experiment_runs_on_a_track_file() {
+        let ws = Workspace::new("track-file");
+        let mut config = config(2);
+        config.track.file = Some("tracks/hairpin.json".to_string());
+        let mut pg = ws.polygon(&config).unwrap();
+        assert!(!pg.worlds[0].way.is_closed());
+        // Spawned at the pose of the track file
+        assert!((pg.worlds[0].car.center.x - 5.0).abs() < 1e-9);
+        pg.run(500);
+    }
+
+    #[test]
+    fn track_file_narrower_than_the_car_is_refused() {
+        let ws = Workspace::new("narrow-track");
+        let mut config = config(1);
+        let mut data = TrackData::load(Path::new("tracks/clover.json")).unwrap();
+        data.half_width = 0.4 * config.car.width;
+        let file = ws.dir.join("narrow.json");
+        data.save(&file).unwrap();
+        config.track.file = Some(file.to_string_lossy().into_owned());
+        let err = ws.polygon(&config).err().unwrap();
+        assert!(err.contains("wide at point"), "{}", err);
+    }
+
+    #[test]
+    fn worlds_get_generated_tracks_of_their_own() {
+        let ws = Workspace::new("trackgen");
+        let mut config = config(3);
+        config.track.name = "generated".to_string();
+        let shared = ws.polygon(&config).unwrap();
+        assert_eq!(shared.worlds[0].way.length(), shared.worlds[2].way.length());
+
+        let mut gc = TrackGenConfig::default();
+        gc.per_world = true;
+        config.track.generator = Some(gc);
+        let mut own = ws.polygon(&config).unwrap();
+        assert_eq!(own.worlds[0].way.length(), shared.worlds[0].way.length());
+        assert!(own.worlds[0].way.length() != own.worlds[2].way.length());
+        own.run(200);
+    }
+
+    #[test]
+    fn evaluation_is_reproducible_and_does_not_learn() {
+        let ws = Workspace::new("eval");
+        let pg = ws.polygon(&config(1)).unwrap();
+        let state = vec![0.1; pg.worlds[0].state.len()];
+        let action = pg.learner.ac_fn()(&state);
+
+        let mut opts = EvalOptions::new(3, 7);
+        opts.max_steps = Some(100);
+        let a = pg.evaluate(&opts).unwrap();
+        let b = pg.evaluate(&opts).unwrap();
+        assert_eq!(a.episodes.len(), 3);
+        assert_eq!(a.mean_return, b.mean_return);
+        assert_eq!(a.mean_distance, b.mean_distance);
+        assert!(a.episodes.iter().all(|e| e.steps <= 100));
+        assert_eq!(pg.learner.ac_fn()(&state), action);
+    }
+
+    #[test]
+    fn way_point_keeps_up_with_a_fast_car() {
+        let ws = Workspace::new("fast-car");
+        let mut config = config(1);
+        // Straight way of 5 unit segments
+        let data = TrackData {
+            points: (0..21).map(|i| [5.0 * i as f64, 0.0]).collect(),
+            scale: 1.0,
+            half_width: 5.0,
+            widths: None,
+            join: None,
+            miter_limit: None,
+            closed: false,
+            spawns: Some(vec![[10.0, 0.0, 1.0, 0.0]]),
+            obstacles: None,
+        };
+        let file = ws.dir.join("straight.json");
+        data.save(&file).unwrap();
+        config.track.file = Some(file.to_string_lossy().into_owned());
+        let mut pg = ws.polygon(&config).unwrap();
+        let world = &mut pg.worlds[0];
+        // Speed is not limited: 1.5 * WAY_REACH in a step of 0.1
+        world.act(&[15.0 * WAY_REACH, 0.0]);
+        assert!(!world.car.hit);
+        assert!((world.car.center.x - 10.0 - 1.5 * WAY_REACH).abs() < 1e-9);
+        let offset = world.way.offset(&world.old_way_point, &world.way_point);
+        assert!((offset - 1.5 * WAY_REACH).abs() < 1e-9, "{}", offset);
+    }
+
+    #[test]
+    fn recordings_keep_the_evaluation_overrides() {
+        let ws = Workspace::new("eval-recording");
+        let pg = ws.polygon(&config(1)).unwrap();
+        let mut opts = EvalOptions::new(1, 7);
+        opts.max_steps = Some(50);
+        opts.spawn = Some(SpawnConfig {
+            poses: vec![[-110.0, 20.0, 0.0, -1.0]],
+            random_along_way: false,
+            max_shift: 0.0,
+            max_angle: 0.0,
+        });
+        opts.record = Some(ws.dir.join("recordings"));
+        pg.evaluate(&opts).unwrap();
+        let recording = Recording::load(&ws.dir.join("recordings/episode-0000.json")).unwrap();
+        assert_eq!(recording.episode.termination.max_steps, Some(50));
+        assert_eq!(recording.episode.spawn.poses, vec![[-110.0, 20.0, 0.0, -1.0]]);
+        let frames = recording.resimulate().unwrap();
+        assert_eq!(recording.divergence(&frames, 1e-6), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Proximal policy optimization: a Gaussian policy with learned
+//! log standard deviations, trained on fixed-length rollouts of all
+//! the worlds with the clipped objective and GAE advantages.
+use rand::distributions::{Normal, IndependentSample};
+use rand::{Rng, StdRng};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path;
+use rustc_serialize::json;
+use checkpoint::{Layers, read_json, write_json};
+use seed;
+use approx::{Approx, ApproxConfig};
+use mlp::{Mlp, Activation, Init};
+use learner::Learner;
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct PpoConfig {
+    /// Steps of every world per update
+    pub rollout: usize,
+    /// Passes over the rollouts per update
+    pub epochs: u32,
+    pub minibatch: usize,
+    /// The policy ratio is clipped to 1 - clip .. 1 + clip
+    pub clip: f64,
+    /// GAE lambda
+    pub lambda: f64,
+    pub policy_lr: f64,
+    pub value_lr: f64,
+    pub entropy_coef: f64,
+    pub normalize_advantages: bool,
+}
+
+impl PpoConfig {
+    pub fn default() -> PpoConfig {
+        PpoConfig {
+            rollout: 128,
+            epochs: 4,
+            minibatch: 64,
+            clip: 0.2,
+            lambda: 0.95,
+            policy_lr: 0.01,
+            value_lr: 0.1,
+            entropy_coef: 0.0,
+            normalize_advantages: true,
+        }
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct PpoState {
+    gamma: f64,
+    log_std: Vec<f64>,
+    updates: u64,
+}
+
+struct Step {
+    state: Vec<f64>,
+    action: Vec<f64>,
+    reward: f64,
+    next_state: Vec<f64>,
+    terminal: bool,
+    /// The world starts a new episode after this step
+    end: bool,
+}
+
+// Step of a rollout prepared for the update
+struct Sample {
+    state: Vec<f64>,
+    action: Vec<f64>,
+    log_prob: f64,
+    advantage: f64,
+    ret: f64,
+}
+
+/// Like `Ddpg`, uses pure-Rust perceptrons whatever the
+/// backend in the approximator config
+pub struct Ppo {
+    policy: Rc<RefCell<Mlp>>,
+    value: Rc<RefCell<Mlp>>,
+    /// Steps of the current rollout of every world
+    rollouts: Vec<Vec<Step>>,
+    config: PpoConfig,
+    pub state: PpoState,
+    rng: StdRng,
+}
+
+fn log_prob(mu: &[f64], log_std: &[f64], action: &[f64]) -> f64 {
+    let mut lp = 0.0;
+    for i in 0..mu.len() {
+        let z = (action[i] - mu[i]) / log_std[i].exp();
+        lp -= 0.5 * z * z + log_std[i];
+    }
+    lp
+}
+
+impl Ppo {
+    /// Learner updated on the rollouts of the `worlds` worlds
+    pub fn new(state_dim: usize, action_dim: usize, worlds: usize,
+               approx: &ApproxConfig, config: &PpoConfig,
+               gamma: f64, sigma: f64, seed: u64) -> Result<Ppo, String> {
+        let hidden = try!(Activation::from_name(&approx.activation)
+                          .ok_or(format!("Unknown activation: {}", approx.activation)));
+        let init = match approx.init.as_ref() {
+            "uniform" => Init::Uniform(approx.init_scale),
+            "xavier" => Init::Xavier,
+            name => return Err(format!("Unknown initialization: {}", name))
+        };
+        let sizes = |inputs, outputs| {
+            let mut sizes = vec![inputs];
+            sizes.extend(approx.hidden.iter().cloned());
+            sizes.push(outputs);
+            sizes
+        };
+        let policy = Mlp::new(&sizes(state_dim, action_dim), hidden, Activation::Tanh, init,
+                              config.policy_lr, &mut seed::rng(seed, seed::AC_NET));
+        let value = Mlp::new(&sizes(state_dim, 1), hidden, Activation::Linear, init,
+                             config.value_lr, &mut seed::rng(seed, seed::V_NET));
+        Ok(Ppo {
+            policy: Rc::new(RefCell::new(policy)),
+            value: Rc::new(RefCell::new(value)),
+            rollouts: (0..worlds).map(|_| Vec::with_capacity(config.rollout)).collect(),
+            config: config.clone(),
+            state: PpoState {
+                gamma: gamma,
+                log_std: vec![sigma.ln(); action_dim],
+                updates: 0
+            },
+            rng: seed::rng(seed, seed::LEARNER),
+        })
+    }
+
+    fn rollouts_full(&self) -> bool {
+        self.rollouts.iter().all(|r| r.len() >= self.config.rollout)
+    }
+
+    // Advantages and returns of the rollouts, which are then emptied
+    fn samples(&mut self) -> Vec<Sample> {
+        let gamma = self.state.gamma;
+        let lambda = self.config.lambda;
+        let policy = self.policy.borrow();
+        let value = self.value.borrow();
+        let mut samples = Vec::new();
+        for rollout in self.rollouts.iter_mut() {
+            let mut world_samples = Vec::with_capacity(rollout.len());
+            let mut adv = 0.0;
+            for (k, st) in rollout.iter().enumerate().rev() {
+                let v = value.call(&st.state)[0];
+                let delta = if st.terminal {
+                    st.reward - v
+                } else {
+                    st.reward + gamma * value.call(&st.next_state)[0] - v
+                };
+                // Nothing is carried over from the next episode
+                // or from beyond the rollout
+                adv = if st.end || k + 1 == rollout.len() {
+                    delta
+                } else {
+                    delta + gamma * lambda * adv
+                };
+                world_samples.push(Sample {
+                    state: st.state.clone(),
+                    action: st.action.clone(),
+                    log_prob: log_prob(&policy.call(&st.state), &self.state.log_std, &st.action),
+                    advantage: adv,
+                    ret: adv + v,
+                });
+            }
+            rollout.clear();
+            samples.extend(world_samples.into_iter().rev());
+        }
+        if self.config.normalize_advantages && samples.len() > 1 {
+            let n = samples.len() as f64;
+            let mean = samples.iter().map(|s| s.advantage).sum::<f64>() / n;
+            let var = samples.iter().map(|s| (s.advantage - mean).powi(2)).sum::<f64>() / n;
+            let std = var.sqrt() + 1e-8;
+            for s in samples.iter_mut() {
+                s.advantage = (s.advantage - mean) / std;
+            }
+        }
+        samples
+    }
+
+    fn update(&mut self) {
+        let samples = self.samples();
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        let mb = if self.config.minibatch > 0 { self.config.minibatch } else { samples.len() };
+        let (lo, hi) = (1.0 - self.config.clip, 1.0 + self.config.clip);
+        for _ in 0..self.config.epochs {
+            self.rng.shuffle(&mut order);
+            for batch in order.chunks(mb) {
+                let n = batch.len() as f64;
+                let lr = self.config.policy_lr / n;
+                let mut log_std_grad = vec![0.0; self.state.log_std.len()];
+                let mut policy = self.policy.borrow_mut();
+                let mut value = self.value.borrow_mut();
+                for &i in batch.iter() {
+                    let s = &samples[i];
+                    let mu = policy.call(&s.state);
+                    let log_std = &self.state.log_std;
+                    let ratio = (log_prob(&mu, log_std, &s.action) - s.log_prob).exp();
+                    // The clipped objective has no gradient where
+                    // the ratio is clipped on the side of the advantage
+                    let clipped = (s.advantage > 0.0 && ratio > hi)
+                        || (s.advantage < 0.0 && ratio < lo);
+                    // Steps go along the natural gradient of the Gaussian,
+                    // so they do not grow as the deviation shrinks
+                    if !clipped {
+                        let w = s.advantage * ratio;
+                        let mut g = Vec::with_capacity(mu.len());
+                        for j in 0..mu.len() {
+                            let var = (2.0 * log_std[j]).exp();
+                            let d = s.action[j] - mu[j];
+                            g.push(w * d);
+                            log_std_grad[j] += 0.5 * w * (d * d / var - 1.0);
+                        }
+                        policy.ascend(&s.state, &g, lr);
+                    }
+                    let v = value.call(&s.state)[0];
+                    value.ascend(&s.state, &[s.ret - v], self.config.value_lr / n);
+                }
+                for (l, g) in self.state.log_std.iter_mut().zip(log_std_grad.iter()) {
+                    *l += lr * (g + n * self.config.entropy_coef);
+                }
+            }
+        }
+        self.state.updates += 1;
+    }
+}
+
+impl Learner for Ppo {
+    fn get_action(&mut self, _world: usize, state: &Vec<f64>, _wander_more: bool) -> Vec<f64> {
+        let mut a = self.policy.borrow().call(state);
+        for (x, l) in a.iter_mut().zip(self.state.log_std.iter()) {
+            *x += Normal::new(0.0, l.exp()).ind_sample(&mut self.rng);
+        }
+        a
+    }
+
+    fn step(&mut self, world: usize, old_state: &Vec<f64>, new_state: &Vec<f64>,
+            action: &Vec<f64>, reward: f64, terminal: bool) {
+        self.rollouts[world].push(Step {
+            state: old_state.clone(),
+            action: action.clone(),
+            reward: reward,
+            next_state: new_state.clone(),
+            terminal: terminal,
+            end: terminal
+        });
+        if self.rollouts_full() {
+            self.update();
+        }
+    }
+
+    fn episode_end(&mut self, world: usize) {
+        if let Some(st) = self.rollouts.get_mut(world).and_then(|r| r.last_mut()) {
+            st.end = true;
+        }
+    }
+
+    /// Steps of unfinished rollouts are not saved
+    fn save(&self, dir: &path::Path) -> Result<(), String> {
+        try!(write_json(&dir.join("ppo.state"), &self.state));
+        try!(self.policy.borrow().save(&dir.join("policy.net")));
+        self.value.borrow().save(&dir.join("value.net"))
+    }
+
+    fn load(&mut self, dir: &path::Path) -> Result<(), String> {
+        let state: PpoState = try!(read_json(&dir.join("ppo.state")));
+        if state.log_std.len() != self.state.log_std.len() {
+            return Err(format!("PPO state has {} actions, expected {}",
+                               state.log_std.len(), self.state.log_std.len()));
+        }
+        try!(self.policy.borrow_mut().load(&dir.join("policy.net")));
+        try!(self.value.borrow_mut().load(&dir.join("value.net")));
+        self.state = state;
+        Ok(())
+    }
+
+    fn networks(&self) -> Vec<Layers> {
+        vec![Layers { name: "policy".to_string(), sizes: self.policy.borrow().sizes() },
+             Layers { name: "value".to_string(), sizes: self.value.borrow().sizes() }]
+    }
+
+    fn sigma(&self) -> f64 {
+        let n = self.state.log_std.len() as f64;
+        self.state.log_std.iter().map(|l| l.exp()).sum::<f64>() / n
+    }
+
+    fn v_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
+        let value = self.value.clone();
+        Box::new(move |x| value.borrow().call(x))
+    }
+
+    fn ac_fn(&self) -> Box<Fn(&Vec<f64>) -> Vec<f64>> {
+        let policy = self.policy.clone();
+        Box::new(move |x| policy.borrow().call(x))
+    }
+
+    fn print(&self) {
+        println!("PPO state: {}", json::encode(&self.state).unwrap());
+        println!("POLICY: ------------------------------");
+        self.policy.borrow().print();
+        println!("VALUE: ------------------------------");
+        self.value.borrow().print();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx::ApproxConfig;
+    use learner::Learner;
+
+    #[test]
+    fn ppo_finds_the_best_action() {
+        let mut config = PpoConfig::default();
+        config.rollout = 32;
+        let mut approx = ApproxConfig::default();
+        approx.hidden = vec![16];
+        approx.init = "xavier".to_string();
+        let mut ppo = Ppo::new(1, 1, 4, &approx, &config, 0.9, 0.5, 3).unwrap();
+        let s = vec![0.0];
+        // One-step episodes of 4 worlds rewarding the action 0.5
+        for _ in 0..3000 {
+            for w in 0..4 {
+                let a = ppo.get_action(w, &s, false);
+                let r = -(a[0] - 0.5) * (a[0] - 0.5);
+                ppo.step(w, &s, &s, &a, r, true);
+                ppo.episode_end(w);
+            }
+        }
+        let a = ppo.ac_fn()(&s)[0];
+        assert!((a - 0.5).abs() < 0.1, "{}", a);
+        assert!(ppo.sigma() < 0.5);
+    }
+
+    #[test]
+    fn update_waits_for_every_world() {
+        let mut config = PpoConfig::default();
+        config.rollout = 1;
+        let mut ppo = Ppo::new(1, 1, 3, &ApproxConfig::default(), &config, 0.9, 0.5, 3).unwrap();
+        let s = vec![0.0];
+        for w in 0..3 {
+            assert_eq!(ppo.state.updates, 0);
+            let a = ppo.get_action(w, &s, false);
+            ppo.step(w, &s, &s, &a, 1.0, false);
+        }
+        assert_eq!(ppo.state.updates, 1);
+        assert!(ppo.rollouts.iter().all(|r| r.is_empty()));
+    }
+}
@@ -0,0 +1,220 @@
+//! Recorded episodes. A recording keeps the configuration, the world
+//! and the start pose, so the episode can be simulated again from its
+//! actions and compared with what was recorded, or just drawn frame
+//! by frame.
+use std::fs::File;
+use std::io::prelude::*;
+use std::path;
+use std::sync::Arc;
+use rustc_serialize::json;
+use geom::Pt;
+use polygon::{World, build_track, action_ranges};
+use config::Config;
+use episode::EpisodeConfig;
+use observation::Observer;
+use reward::{RewardFn, WeightedReward};
+
+/// Version of the recording format written by this build
+pub const VERSION: u32 = 2;
+
+/// State of the car after a step, and what led to it
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Frame {
+    pub center: [f64; 2],
+    pub course: [f64; 2],
+    pub speed: f64,
+    pub wheels_angle: f64,
+    pub action: Vec<f64>,
+    pub reward: f64,
+    /// Distances to the walls along the rays; negative if none is hit
+    pub rays: Vec<f64>,
+}
+
+impl Frame {
+    pub fn of(world: &World) -> Frame {
+        let car = &world.car;
+        Frame {
+            center: [car.center.x, car.center.y],
+            course: [car.course.x, car.course.y],
+            speed: car.speed,
+            wheels_angle: car.wheels_angle,
+            action: world.last_action.clone(),
+            reward: world.reward(),
+            // Rays that hit nothing are 1.0e20 long, which the JSON
+            // decoder cannot read back
+            rays: car.isxs.iter().map(|i| if i.dist < 1.0e19 { i.dist } else { -1.0 }).collect(),
+        }
+    }
+
+    pub fn center(&self) -> Pt {
+        Pt::new(self.center[0], self.center[1])
+    }
+
+    pub fn course(&self) -> Pt {
+        Pt::new(self.course[0], self.course[1])
+    }
+}
+
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Recording {
+    pub version: u32,
+    /// Experiment the episode comes from
+    pub config: Config,
+    /// World whose track the episode was driven on
+    pub world: usize,
+    /// Episode configuration of the world, with the spawn poses of
+    /// the track and the overrides of an evaluation
+    pub episode: EpisodeConfig,
+    /// Pose before the first step: x, y, course x, course y
+    pub start: [f64; 4],
+    pub frames: Vec<Frame>,
+    /// Why the episode has finished, if it has
+    pub done: Option<String>,
+}
+
+impl Recording {
+    /// Starts recording the episode that world `index` has just begun
+    pub fn new(config: &Config, index: usize, world: &World) -> Recording {
+        let car = &world.car;
+        Recording {
+            version: VERSION,
+            config: config.clone(),
+            world: index,
+            episode: (*world.episode_config).clone(),
+            start: [car.center.x, car.center.y, car.course.x, car.course.y],
+            frames: Vec::new(),
+            done: None,
+        }
+    }
+
+    /// Records the step the world has just made
+    pub fn record(&mut self, world: &World) {
+        self.frames.push(Frame::of(world));
+    }
+
+    /// Writes the recording as JSON on a single line
+    pub fn save(&self, filename: &path::Path) -> Result<(), String> {
+        let js = try!(json::encode(self).map_err(|e| format!("{}", e)));
+        File::create(filename)
+            .and_then(|mut f| f.write_all(js.as_bytes()))
+            .map_err(|e| format!("Cannot write {:?}: {}", filename, e))
+    }
+
+    pub fn load(filename: &path::Path) -> Result<Recording, String> {
+        let mut js = String::new();
+        try!(File::open(filename)
+             .and_then(|mut f| f.read_to_string(&mut js))
+             .map_err(|e| format!("Cannot read {:?}: {}", filename, e)));
+        let recording: Recording = try!(json::decode(&js)
+                                        .map_err(|e| format!("Bad recording {:?}: {}", filename, e)));
+        if recording.version != VERSION {
+            return Err(format!("Recording version {} is not supported (expected {})",
+                               recording.version, VERSION));
+        }
+        Ok(recording)
+    }
+
+    /// Recorded world with the car at the start pose
+    pub fn world(&self) -> Result<World, String> {
+        let config = &self.config;
+        // The recorded episode configuration has the spawn poses already
+        let (walls, walls_grid, way) = try!(build_track(&config.track, config.seed, self.world,
+                                                        config.car.width,
+                                                        &mut config.episode.clone()));
+        let action_dim = action_ranges().len();
+        let nrays = config.sensors.nrays;
+        let observer = Arc::new(try!(Observer::from_config(&config.observation,
+                                                          nrays, action_dim)));
+        let reward_fn: Arc<RewardFn> = Arc::new(try!(WeightedReward::from_config(&config.reward)));
+        let mut world = World::new(&config.car, nrays, walls, walls_grid, way,
+                                   observer, reward_fn,
+                                   Arc::new(self.episode.clone()), action_dim);
+        world.reset_to(Pt::new(self.start[0], self.start[1]),
+                       Pt::new(self.start[2], self.start[3]));
+        Ok(world)
+    }
+
+    /// Frames of the episode simulated again from the recorded actions
+    pub fn resimulate(&self) -> Result<Vec<Frame>, String> {
+        let mut world = try!(self.world());
+        Ok(self.frames.iter().map(|f| {
+            world.act(&f.action);
+            Frame::of(&world)
+        }).collect())
+    }
+
+    /// First frame where the car of `frames` is farther than
+    /// `eps` from the recorded one
+    pub fn divergence(&self, frames: &[Frame], eps: f64) -> Option<usize> {
+        self.frames.iter().zip(frames.iter())
+            .position(|(a, b)| (a.center() - b.center()).norm() > eps
+                      || (a.course() - b.course()).norm() > eps)
+            .or(if frames.len() != self.frames.len() {
+                Some(frames.len().min(self.frames.len()))
+            } else {
+                None
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use config::Config;
+    use trackgen::TrackGenConfig;
+
+    fn recording(config: &Config, world: usize) -> Recording {
+        Recording {
+            version: VERSION,
+            config: config.clone(),
+            world: world,
+            episode: config.episode.clone(),
+            start: [-110.0, 0.0, 0.0, 1.0],
+            frames: Vec::new(),
+            done: None
+        }
+    }
+
+    #[test]
+    fn recorded_episode_resimulates_the_same() {
+        let config = Config::default();
+        let mut world = recording(&config, 0).world().unwrap();
+        let mut recording = Recording::new(&config, 0, &world);
+        for i in 0..200 {
+            let turn = if i < 100 { 0.3 } else { -0.5 };
+            world.act(&[2.0, turn]);
+            recording.record(&world);
+        }
+
+        let filename = env::temp_dir().join("polygon-recording-test.json");
+        recording.save(&filename).unwrap();
+        let loaded = Recording::load(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        // JSON numbers may come back a unit in the last place off
+        assert_eq!(loaded.frames.len(), recording.frames.len());
+        assert_eq!(recording.divergence(&loaded.frames, 1e-9), None);
+
+        let frames = loaded.resimulate().unwrap();
+        assert_eq!(loaded.divergence(&frames, 1e-6), None);
+        assert_eq!(loaded.divergence(&frames[..10], 1e-6), Some(10));
+    }
+
+    #[test]
+    fn recording_keeps_the_track_of_its_world() {
+        let mut config = Config::default();
+        config.track.name = "generated".to_string();
+        let mut gc = TrackGenConfig::default();
+        gc.per_world = true;
+        config.track.generator = Some(gc);
+        config.episode.spawn.random_along_way = true;
+        let first = recording(&config, 0).world().unwrap();
+        let world = recording(&config, 2).world().unwrap();
+        assert!(world.way.length() != first.way.length());
+        let recorded = Recording::new(&config, 2, &world);
+        assert_eq!(recorded.world, 2);
+        assert!(recorded.episode.spawn.random_along_way);
+        assert_eq!(recorded.world().unwrap().way.length(), world.way.length());
+    }
+}
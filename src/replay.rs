@@ -0,0 +1,221 @@
+use rand::{Rng, StdRng};
+
+/// Transition between normalized states
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub state: Vec<f64>,
+    pub action: Vec<f64>,
+    pub reward: f64,
+    pub next_state: Vec<f64>,
+    pub terminal: bool,
+}
+
+/// Replay of past transitions by Cacla
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct ReplayConfig {
+    pub capacity: usize,
+    pub batch_size: usize,
+    /// Minibatches replayed per step; fractions accumulate
+    pub ratio: f64,
+    /// Sample in proportion to the TD error instead of uniformly.
+    /// The samples are not weighted back by importance sampling.
+    pub prioritized: bool,
+    /// How much the priorities matter: 0 - uniform, 1 - proportional
+    pub alpha: f64,
+}
+
+impl ReplayConfig {
+    pub fn default() -> ReplayConfig {
+        ReplayConfig {
+            capacity: 100000,
+            batch_size: 16,
+            ratio: 1.0,
+            prioritized: false,
+            alpha: 0.6,
+        }
+    }
+}
+
+// Binary tree of sums of the priorities, leaves are the priorities
+struct SumTree {
+    nodes: Vec<f64>,
+    leaves: usize,
+}
+
+impl SumTree {
+    fn new(capacity: usize) -> SumTree {
+        let mut leaves = 1;
+        while leaves < capacity {
+            leaves *= 2;
+        }
+        SumTree {
+            nodes: vec![0.0; 2 * leaves],
+            leaves: leaves
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    fn set(&mut self, i: usize, p: f64) {
+        let mut k = i + self.leaves;
+        self.nodes[k] = p;
+        while k > 1 {
+            k /= 2;
+            self.nodes[k] = self.nodes[2 * k] + self.nodes[2 * k + 1];
+        }
+    }
+
+    // Leaf where the running sum of the priorities passes `x`
+    fn find(&self, mut x: f64) -> usize {
+        let mut k = 1;
+        while k < self.leaves {
+            if x < self.nodes[2 * k] || self.nodes[2 * k + 1] <= 0.0 {
+                k = 2 * k;
+            } else {
+                x -= self.nodes[2 * k];
+                k = 2 * k + 1;
+            }
+        }
+        k - self.leaves
+    }
+}
+
+/// Priorities of a prioritized buffer are `(|td error| + PRIORITY_EPS)^alpha`
+const PRIORITY_EPS: f64 = 0.01;
+
+/// The most recent transitions; the oldest are overwritten when it is full
+pub struct ReplayBuffer {
+    items: Vec<Transition>,
+    capacity: usize,
+    next: usize,
+    /// Priorities of a prioritized buffer and their exponent
+    priorities: Option<(SumTree, f64)>,
+    max_priority: f64,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> ReplayBuffer {
+        ReplayBuffer {
+            items: Vec::with_capacity(capacity),
+            capacity: capacity,
+            next: 0,
+            priorities: None,
+            max_priority: 1.0
+        }
+    }
+
+    /// Buffer sampling transitions in proportion to their priorities.
+    /// New transitions get the highest priority seen so far.
+    pub fn prioritized(capacity: usize, alpha: f64) -> ReplayBuffer {
+        let mut buffer = ReplayBuffer::new(capacity);
+        buffer.priorities = Some((SumTree::new(capacity), alpha));
+        buffer
+    }
+
+    pub fn from_config(config: &ReplayConfig) -> Result<ReplayBuffer, String> {
+        if config.capacity == 0 {
+            return Err("Replay buffer capacity must be positive".to_string());
+        }
+        Ok(if config.prioritized {
+            ReplayBuffer::prioritized(config.capacity, config.alpha)
+        } else {
+            ReplayBuffer::new(config.capacity)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Adds the transition and returns its index
+    pub fn push(&mut self, t: Transition) -> usize {
+        let i = self.next;
+        if self.items.len() < self.capacity {
+            self.items.push(t);
+        } else {
+            self.items[i] = t;
+        }
+        self.next = (i + 1) % self.capacity;
+        if let Some((ref mut tree, _)) = self.priorities {
+            tree.set(i, self.max_priority);
+        }
+        i
+    }
+
+    pub fn get(&self, i: usize) -> &Transition {
+        &self.items[i]
+    }
+
+    /// Sets the priority of a transition from its TD error;
+    /// does nothing for a uniform buffer
+    pub fn set_td_error(&mut self, i: usize, td_error: f64) {
+        if let Some((ref mut tree, alpha)) = self.priorities {
+            let p = (td_error.abs() + PRIORITY_EPS).powf(alpha);
+            if p > self.max_priority {
+                self.max_priority = p;
+            }
+            tree.set(i, p);
+        }
+    }
+
+    /// Priority of a transition in a prioritized buffer
+    #[cfg(test)]
+    pub fn priority(&self, i: usize) -> Option<f64> {
+        self.priorities.as_ref().map(|&(ref tree, _)| tree.nodes[i + tree.leaves])
+    }
+
+    /// Indices of `n` transitions drawn with replacement,
+    /// uniformly or in proportion to their priorities
+    pub fn sample(&self, n: usize, rng: &mut StdRng) -> Vec<usize> {
+        match self.priorities {
+            Some((ref tree, _)) => (0..n).map(|_| {
+                let i = tree.find(rng.gen_range(0.0, tree.total()));
+                if i < self.items.len() { i } else { self.items.len() - 1 }
+            }).collect(),
+            None => (0..n).map(|_| rng.gen_range(0, self.items.len())).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{SeedableRng, StdRng};
+
+    fn transition(reward: f64) -> Transition {
+        Transition {
+            state: vec![],
+            action: vec![],
+            reward: reward,
+            next_state: vec![],
+            terminal: false
+        }
+    }
+
+    #[test]
+    fn prioritized_sampling_follows_td_errors() {
+        let mut rng: StdRng = SeedableRng::from_seed(&[1usize][..]);
+        let mut buffer = ReplayBuffer::prioritized(5, 1.0);
+        for i in 0..7 {
+            buffer.push(transition(i as f64));
+        }
+        // Indices 0 and 1 hold the transitions 5 and 6
+        assert_eq!(buffer.get(0).reward, 5.0);
+        for i in 0..5 {
+            buffer.set_td_error(i, if i == 3 { 2.99 } else { 0.0 });
+        }
+        let samples = buffer.sample(10000, &mut rng);
+        let hits = samples.iter().filter(|&&i| i == 3).count() as f64;
+        // 3 / (3 + 4 * 0.01)
+        assert!((hits / 10000.0 - 0.987).abs() < 0.01, "{}", hits);
+    }
+
+    #[test]
+    fn zero_capacity_is_refused() {
+        let mut config = ReplayConfig::default();
+        config.capacity = 0;
+        assert!(ReplayBuffer::from_config(&config).is_err());
+    }
+}
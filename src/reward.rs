@@ -0,0 +1,259 @@
+use cacla::Range;
+use polygon::{World, RAY_RANGE};
+
+/// Reward of a world after a step, made of named terms.
+pub trait RewardFn: Send + Sync {
+    fn term_names(&self) -> Vec<&str>;
+
+    /// Returns the total reward and stores the values
+    /// of the separate terms in `terms`
+    fn reward(&self, world: &World, terms: &mut [f64]) -> f64;
+
+    /// Expected range of the reward, used to normalize it for the learner
+    fn range(&self) -> Range;
+}
+
+/// Building blocks of rewards. Every term is signed the way
+/// it contributes to the reward, penalties are negative.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Term {
+    Speed,            // speed, backward speed counts as positive at half rate
+    SpeedSq,          // -speed^2
+    Progress,         // distance travelled along the way
+    WallProximity,    // min(d * (1 - 0.0099 * d), 0) over ray distances
+    WallGauss,        // -exp(-d^2 / (2 * sigma^2)) of the nearest wall
+    WheelsAbs,        // -|wheels angle|
+    WheelsSq,         // -wheels angle^2
+    ActionPenalty,    // -Car::action_penalty
+    ActionPenalty2,   // -Car::action_penalty2
+    ActionPenalty3Sq, // -Car::action_penalty3^2
+    Stopped,          // -1 if the car does not move
+    Checkpoint,       // 1 when the car drives through its next checkpoint
+}
+
+const TERMS: [Term; 12] = [Term::Speed, Term::SpeedSq, Term::Progress,
+                           Term::WallProximity, Term::WallGauss,
+                           Term::WheelsAbs, Term::WheelsSq,
+                           Term::ActionPenalty, Term::ActionPenalty2,
+                           Term::ActionPenalty3Sq, Term::Stopped,
+                           Term::Checkpoint];
+
+impl Term {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Term::Speed => "speed",
+            Term::SpeedSq => "speed_sq",
+            Term::Progress => "progress",
+            Term::WallProximity => "wall_proximity",
+            Term::WallGauss => "wall_gauss",
+            Term::WheelsAbs => "wheels_abs",
+            Term::WheelsSq => "wheels_sq",
+            Term::ActionPenalty => "action_penalty",
+            Term::ActionPenalty2 => "action_penalty2",
+            Term::ActionPenalty3Sq => "action_penalty3_sq",
+            Term::Stopped => "stopped",
+            Term::Checkpoint => "checkpoint",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Term> {
+        TERMS.iter().find(|t| t.name() == name).cloned()
+    }
+
+    pub fn value(&self, world: &World) -> f64 {
+        let car = &world.car;
+        let speed = car.speed;
+        let wheels = car.wheels_angle;
+        match *self {
+            Term::Speed => if speed > 0.0 { speed } else { -0.5 * speed },
+            Term::SpeedSq => -speed * speed,
+            Term::Progress => world.way.offset(&world.old_way_point, &world.way_point),
+            Term::WallProximity => {
+                let mut r = 0.0;
+                for isx in car.isxs.iter() {
+                    let d = clip_ray(isx.dist);
+                    r = min(d * (1.0 - 0.0099 * d), r);
+                }
+                r
+            },
+            Term::WallGauss => {
+                let mut min_dist = 1.0e20;
+                for isx in car.isxs.iter() {
+                    let d = clip_ray(isx.dist);
+                    if d >= 0.0 && d < min_dist {
+                        min_dist = d;
+                    }
+                }
+                let sigma = 2.0 / 3.0;
+                -(-min_dist * min_dist / (2.0 * sigma * sigma)).exp()
+            },
+            Term::WheelsAbs => -wheels.abs(),
+            Term::WheelsSq => -wheels * wheels,
+            Term::ActionPenalty => -car.action_penalty(&world.last_action),
+            Term::ActionPenalty2 => -car.action_penalty2(&world.last_action),
+            Term::ActionPenalty3Sq => {
+                let p = car.action_penalty3(&world.last_action);
+                -p * p
+            },
+            Term::Stopped => if speed.abs() < 0.001 { -1.0 } else { 0.0 },
+            Term::Checkpoint => world.laps.passed as f64,
+        }
+    }
+}
+
+fn clip_ray(d: f64) -> f64 {
+    if d < RAY_RANGE { d } else { RAY_RANGE }
+}
+
+fn min(a: f64, b: f64) -> f64 {
+    if a > b {
+        b
+    } else {
+        a
+    }
+}
+
+/// Named term with its weight, as written in configuration
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TermConfig {
+    pub name: String,
+    pub weight: f64,
+}
+
+/// Reward configuration: an optional preset, terms overriding
+/// or extending its weights (zero weight removes a term),
+/// and an optional range overriding the preset one.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct RewardConfig {
+    pub preset: Option<String>,
+    pub terms: Option<Vec<TermConfig>>,
+    pub range: Option<[f64; 2]>,
+}
+
+impl RewardConfig {
+    pub fn preset(name: &str) -> RewardConfig {
+        RewardConfig {
+            preset: Some(name.to_string()),
+            terms: None,
+            range: None
+        }
+    }
+}
+
+/// Weighted sum of terms
+#[derive(Clone, Debug)]
+pub struct WeightedReward {
+    terms: Vec<(Term, f64)>,
+    range: Range,
+}
+
+impl WeightedReward {
+    pub fn new(terms: Vec<(Term, f64)>, range: Range) -> WeightedReward {
+        WeightedReward {
+            terms: terms,
+            range: range
+        }
+    }
+
+    /// Rewards used by the project so far:
+    /// * "default" - speed near 0.5 with penalties for wheels and jerks
+    /// * "old" - speed with penalties for walls and actions
+    /// * "old_2" - speed with penalties for wheels, stops and actions
+    pub fn preset(name: &str) -> Option<WeightedReward> {
+        match name {
+            "default" => Some(WeightedReward::new(
+                    vec![(Term::Speed, 10.0),
+                         (Term::WallProximity, 20.0),
+                         (Term::WheelsSq, 5.0),
+                         (Term::ActionPenalty3Sq, 1.0),
+                         (Term::SpeedSq, 10.0)],
+                    Range::new(-100.0, 100.0))),
+            "old" => Some(WeightedReward::new(
+                    vec![(Term::Speed, 1.0),
+                         (Term::WallGauss, 1.0),
+                         (Term::ActionPenalty, 1.0),
+                         (Term::WheelsAbs, 1.0),
+                         (Term::ActionPenalty2, 2.0)],
+                    Range::new(-4.0, 1.0))),
+            "old_2" => Some(WeightedReward::new(
+                    vec![(Term::Speed, 1.0),
+                         (Term::WheelsAbs, 2.0),
+                         (Term::Stopped, 1.0),
+                         (Term::ActionPenalty, 1.0)],
+                    Range::new(-4.0, 1.0))),
+            _ => None
+        }
+    }
+
+    pub fn from_config(config: &RewardConfig) -> Result<WeightedReward, String> {
+        let mut reward = match config.preset {
+            Some(ref name) => try!(WeightedReward::preset(name)
+                    .ok_or(format!("Unknown reward preset: {}", name))),
+            None => match config.range {
+                Some(_) => WeightedReward::new(Vec::new(), Range::zero()),
+                None => return Err("Reward range is required without a preset".to_string())
+            }
+        };
+        if let Some(ref terms) = config.terms {
+            for tc in terms {
+                let term = try!(Term::from_name(&tc.name)
+                        .ok_or(format!("Unknown reward term: {}", tc.name)));
+                reward.terms.retain(|&(t, _)| t != term);
+                if tc.weight != 0.0 {
+                    reward.terms.push((term, tc.weight));
+                }
+            }
+        }
+        if let Some(r) = config.range {
+            reward.range = Range::new(r[0], r[1]);
+        }
+        Ok(reward)
+    }
+
+    pub fn weights(&self) -> Vec<f64> {
+        self.terms.iter().map(|&(_, w)| w).collect()
+    }
+}
+
+impl RewardFn for WeightedReward {
+    fn term_names(&self) -> Vec<&str> {
+        self.terms.iter().map(|&(t, _)| t.name()).collect()
+    }
+
+    fn reward(&self, world: &World, terms: &mut [f64]) -> f64 {
+        let mut r = 0.0;
+        for (i, &(t, w)) in self.terms.iter().enumerate() {
+            terms[i] = t.value(world);
+            r += w * terms[i];
+        }
+        r
+    }
+
+    fn range(&self) -> Range {
+        self.range.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustc_serialize::json;
+
+    #[test]
+    fn config_overrides_preset() {
+        let config: RewardConfig = json::decode(r#"{
+            "preset": "old_2",
+            "terms": [{"name": "stopped", "weight": 0.0},
+                      {"name": "progress", "weight": 5.0},
+                      {"name": "speed", "weight": 2.0}]
+        }"#).unwrap();
+        let reward = WeightedReward::from_config(&config).unwrap();
+        assert_eq!(reward.term_names(), vec!["wheels_abs", "action_penalty", "progress", "speed"]);
+        assert_eq!(reward.weights(), vec![2.0, 1.0, 5.0, 2.0]);
+        assert_eq!(reward.range().lo, -4.0);
+
+        let config = RewardConfig { preset: None, terms: None, range: None };
+        assert!(WeightedReward::from_config(&config).is_err());
+        assert!(WeightedReward::from_config(&RewardConfig::preset("nope")).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+//! Every random source of an experiment gets its own stream
+//! derived from the experiment seed, so that runs with the same
+//! seed are reproducible and the sources do not depend on each other.
+use rand::{SeedableRng, StdRng};
+
+pub const LEARNER: u64 = 1;
+pub const V_NET: u64 = 2;
+pub const AC_NET: u64 = 3;
+pub const REPLAY: u64 = 4;
+/// Spawn poses of the evaluation episodes
+pub const EVAL: u64 = 5;
+/// World `i` uses the stream `WORLDS + i`
+pub const WORLDS: u64 = 1000;
+/// Generated track of world `i` uses the stream `TRACKS + i`;
+/// a track shared by all the worlds is that of world 0
+pub const TRACKS: u64 = 1000000;
+
+pub fn rng(seed: u64, stream: u64) -> StdRng {
+    SeedableRng::from_seed(&[seed as usize, stream as usize][..])
+}
@@ -1,50 +1,284 @@
-use geom::{Figure, Sect, Pt};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path;
+use rustc_serialize::json;
+use geom::{Figure, Pt};
 
+/// Track as written in a track file. Points, poses and obstacles
+/// are in track units and are multiplied by `scale`.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TrackData {
+    /// Points of the center line
+    pub points: Vec<[f64; 2]>,
+    pub scale: f64,
+    /// Distance from the center line to the walls, in world units
+    pub half_width: f64,
+    /// Distances to the walls at each point, in world units;
+    /// replace `half_width`
+    pub widths: Option<Vec<f64>>,
+    /// How the walls turn at the points; `Bisector` if not given
+    pub join: Option<Join>,
+    /// Longest distance of a mitered corner from its point, in half
+    /// widths; 2 if not given
+    pub miter_limit: Option<f64>,
+    /// Whether the last point is joined to the first one. The ends
+    /// of an open track are closed by walls.
+    pub closed: bool,
+    /// Start poses: x, y, course x, course y
+    pub spawns: Option<Vec<[f64; 4]>>,
+    /// Polygons in the way of the cars
+    pub obstacles: Option<Vec<Vec<[f64; 2]>>>,
+}
+
+impl TrackData {
+    pub fn clover(half_width: f64, scale: f64) -> TrackData {
+        TrackData {
+            points: clover_data.to_vec(),
+            scale: scale,
+            half_width: half_width,
+            widths: None,
+            join: None,
+            miter_limit: None,
+            closed: true,
+            spawns: None,
+            obstacles: None,
+        }
+    }
+
+    pub fn load(filename: &path::Path) -> Result<TrackData, String> {
+        let mut js = String::new();
+        try!(File::open(filename)
+             .and_then(|mut f| f.read_to_string(&mut js))
+             .map_err(|e| format!("Cannot read {:?}: {}", filename, e)));
+        let data: TrackData = try!(json::decode(&js)
+                                   .map_err(|e| format!("Bad track file {:?}: {}", filename, e)));
+        let min_points = if data.closed { 3 } else { 2 };
+        if data.points.len() < min_points {
+            return Err(format!("Track {:?} needs at least {} points", filename, min_points));
+        }
+        if let Some(ref widths) = data.widths {
+            if widths.len() != data.points.len() || widths.iter().any(|&w| !(w > 0.0)) {
+                return Err(format!("Track {:?} needs a positive width for each of its {} points",
+                                   filename, data.points.len()));
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn save(&self, filename: &path::Path) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut f| write!(f, "{}", json::as_pretty_json(self)))
+            .map_err(|e| format!("Cannot write {:?}: {}", filename, e))
+    }
+
+    /// Walls along both sides of the way and around the obstacles
+    pub fn walls(&self) -> Figure {
+        let sides = if self.closed {
+            closed_walls(&self.sides())
+        } else {
+            open_walls(&self.sides())
+        };
+        let mut figures = vec![sides];
+        for o in self.obstacles.iter().flat_map(|os| os.iter()) {
+            figures.push(Figure::closed_path(&scaled(o, self.scale)));
+        }
+        Figure::compound(&figures)
+    }
+
+    /// Left and right walls of the way
+    pub fn sides(&self) -> (Wall, Wall) {
+        let points = scaled(&self.points, self.scale);
+        let widths = self.widths.clone().unwrap_or_else(|| vec![self.half_width; points.len()]);
+        offset(&points, &widths, self.closed, self.join.unwrap_or(Join::Bisector),
+               self.miter_limit.unwrap_or(DEFAULT_MITER_LIMIT))
+    }
+
+    pub fn way(&self) -> Way {
+        let way = if self.closed {
+            Way::new(&self.points, self.scale)
+        } else {
+            Way::open(&self.points, self.scale)
+        };
+        way.with_half_widths(self.widths.clone()
+                             .unwrap_or_else(|| vec![self.half_width; self.points.len()]))
+    }
+
+    /// Start poses in world units
+    pub fn spawn_poses(&self) -> Vec<[f64; 4]> {
+        self.spawns.iter().flat_map(|ss| ss.iter())
+            .map(|s| [self.scale * s[0], self.scale * s[1], s[2], s[3]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
 pub fn clover(d: f64, scale: f64) -> Figure {
     make_track(&clover_data, d, scale)
 }
 
-pub fn obstacle(p: Pt, size: f64) -> Figure {
-    let d = 0.5 * size;
-    Figure::closed_path(&[p + Pt::new(d, d),
-                        p + Pt::new(d, -d),
-                        p + Pt::new(-d, -d),
-                        p + Pt::new(-d, d)])
+/// Walls of a closed track of half width `d`, for the tests
+#[cfg(test)]
+pub fn make_track(points0: &[[f64; 2]], d: f64, scale: f64) -> Figure {
+    let points = scaled(points0, scale);
+    let widths = vec![d; points.len()];
+    closed_walls(&offset(&points, &widths, true, Join::Bisector, 0.0))
 }
 
-pub fn make_track(points0: &[[f64; 2]], d: f64, scale: f64) -> Figure {
-    let points = points0.iter()
-        .map(|p| scale * Pt::new(p[0], p[1]))
-        .collect::<Vec<Pt>>();
+fn scaled(points: &[[f64; 2]], scale: f64) -> Vec<Pt> {
+    points.iter().map(|p| scale * Pt::new(p[0], p[1])).collect()
+}
+
+// The paths start at the wall points of the last point
+fn closed_walls(&(ref left, ref right): &(Wall, Wall)) -> Figure {
+    let path = |w: &Wall| {
+        let start = w.at[w.at.len() - 1];
+        let ps: Vec<Pt> = w.points[start..].iter().chain(w.points[..start].iter()).cloned().collect();
+        Figure::closed_path(&ps)
+    };
+    Figure::compound(&[path(left), path(right)])
+}
+
+// Both sides and the ends of a track that does not return
+// to its start, as a single closed path
+fn open_walls(&(ref left, ref right): &(Wall, Wall)) -> Figure {
+    let mut ps = left.points.clone();
+    ps.extend(right.points.iter().rev().cloned());
+    Figure::closed_path(&ps)
+}
+
+/// How a wall turns around a corner of the center line
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Join {
+    /// A point at the half width along the bisector of the turn,
+    /// so the walls come closer at sharp corners
+    Bisector,
+    /// The walls of the segments meet on both sides; on the outside
+    /// a corner longer than the miter limit is cut off
+    Miter,
+    /// The walls meet on the inside and go round the point on the outside
+    Round,
+}
+
+/// Wall on one side of the center line. The wall points of the
+/// point `i` of the center line start at `at[i]`; there are more
+/// than one where a join cuts or rounds a corner.
+#[derive(Clone, Debug)]
+pub struct Wall {
+    pub points: Vec<Pt>,
+    pub at: Vec<usize>,
+}
+
+impl Wall {
+    fn new(n: usize) -> Wall {
+        Wall { points: Vec::with_capacity(n), at: Vec::with_capacity(n) }
+    }
+
+    /// Wall points of the point `i` of the center line
+    pub fn joint(&self, i: usize) -> &[Pt] {
+        let end = if i + 1 < self.at.len() { self.at[i + 1] } else { self.points.len() };
+        &self.points[self.at[i]..end]
+    }
+
+    /// Point of the center line the wall segment `k` belongs to, and
+    /// whether the segment is a part of its joint rather than the
+    /// wall of the center line segment starting there
+    pub fn owner(&self, k: usize) -> (usize, bool) {
+        let i = match self.at.binary_search(&k) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        (i, k + 1 < self.at[i] + self.joint(i).len())
+    }
+}
+
+pub const DEFAULT_MITER_LIMIT: f64 = 2.0;
+
+// Steps of a rounded joint
+const ROUND_STEP: f64 = ::std::f64::consts::PI / 8.0;
+
+/// Left and right walls at the half width `widths[i]` from the point
+/// `i` of the center line. Miter limit is the longest distance of a
+/// mitered corner from its point in half widths.
+pub fn offset(points: &[Pt], widths: &[f64], closed: bool,
+              join: Join, miter_limit: f64) -> (Wall, Wall) {
     let n = points.len();
-    //println!("points={:?}", points);
-    let mut ps1: Vec<Pt> = Vec::with_capacity(n);
-    let mut ps2: Vec<Pt> = Vec::with_capacity(n);
+    let mut left = Wall::new(n);
+    let mut right = Wall::new(n);
     for i in 0..n {
-        let x0 = if i > 1 { points[i - 2] } else { points[n-2+i] };
-        let x1 = if i > 0 { points[i - 1]} else { points[n-1+i] };
-        let x2 = points[i];
-        let y1 = normalized(x1 - x0);
-        let y2 = normalized(x1 - x2);
-        let y = normalized(y1 + y2);
-        let s = vec_prod_sign(y1, y);
-        let z1 = x1 + s*d*y;
-        let z2 = x1 - s*d*y;
-        //println!("x1={:?}, x2={:?}, y2={:?}, s={:?}, z1={:?}", x1, x2, y2, s, z1);
-        ps1.push(z1);
-        ps2.push(z2);
-    }
-    let f1 = Figure::closed_path(ps1.as_ref());
-    let f2 = Figure::closed_path(ps2.as_ref());
-    let mut v = Vec::new();
-    v.push(f1);
-    v.push(f2);
-    /*
-    for p in points {
-        v.push(obstacle(p, 0.1 * scale));
-    }
-    */
-    Figure::compound(v.as_ref())
+        left.at.push(left.points.len());
+        right.at.push(right.points.len());
+        let d = widths[i];
+        if !closed && (i == 0 || i == n - 1) {
+            let (a, b) = if i == 0 { (points[0], points[1]) } else { (points[n-2], points[n-1]) };
+            let y = lperp(normalized(b - a));
+            left.points.push(points[i] + d*y);
+            right.points.push(points[i] - d*y);
+            continue;
+        }
+        let (x0, x1, x2) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+        if join == Join::Bisector {
+            let (z1, z2) = sides(x0, x1, x2, d);
+            left.points.push(z1);
+            right.points.push(z2);
+            continue;
+        }
+        let t0 = normalized(x1 - x0);
+        let t1 = normalized(x2 - x1);
+        let (n0, n1) = (lperp(t0), lperp(t1));
+        let m = n0 + n1;
+        let cos_half = 0.5 * m.norm();
+        if cos_half < 1e-9 {
+            // The line turns back on itself
+            let (z1, z2) = sides(x0, x1, x2, d);
+            left.points.push(z1);
+            right.points.push(z2);
+            continue;
+        }
+        let miter = d / cos_half * normalized(m);
+        // The inner side of a left turn is on the left
+        let left_turn = t0.x * t1.y - t0.y * t1.x > 0.0;
+        let (inner, outer, s) = if left_turn { (&mut left, &mut right, -1.0) } else { (&mut right, &mut left, 1.0) };
+        inner.points.push(x1 - s * miter);
+        let (a, b) = (s * d * n0, s * d * n1);
+        match join {
+            Join::Miter if 1.0 / cos_half <= miter_limit => outer.points.push(x1 + s * miter),
+            Join::Round => {
+                let start = a.y.atan2(a.x);
+                let turn = (a.x * b.y - a.y * b.x).atan2(a.x * b.x + a.y * b.y);
+                let steps = (turn.abs() / ROUND_STEP).ceil().max(1.0) as usize;
+                for k in 0..steps + 1 {
+                    let angle = start + turn * k as f64 / steps as f64;
+                    outer.points.push(x1 + d * Pt::new(angle.cos(), angle.sin()));
+                }
+            },
+            _ => {
+                outer.points.push(x1 + a);
+                outer.points.push(x1 + b);
+            },
+        }
+    }
+    (left, right)
+}
+
+// Points at the distance `d` to the left and to the right of `x1`
+// along the bisector of the turn x0 - x1 - x2
+fn sides(x0: Pt, x1: Pt, x2: Pt, d: f64) -> (Pt, Pt) {
+    let y1 = normalized(x1 - x0);
+    let y2 = normalized(x1 - x2);
+    let b = y1 + y2;
+    let (s, y) = if b.norm() < 1e-9 {
+        // No turn, the bisector is the normal
+        (1.0, lperp(y1))
+    } else {
+        let y = normalized(b);
+        (vec_prod_sign(y1, y), y)
+    };
+    //println!("x1={:?}, x2={:?}, y2={:?}, s={:?}", x1, x2, y2, s);
+    (x1 + s*d*y, x1 - s*d*y)
+}
+
+fn lperp(p: Pt) -> Pt {
+    Pt::new(-p.y, p.x)
 }
 
 fn normalized(v: Pt) -> Pt {
@@ -142,11 +376,25 @@ impl Projection {
 pub struct Way {
     segment_len: Vec<f64>,
     points: Vec<Pt>,
-    count: i32
+    /// Distances from the points to the walls, if known
+    half_widths: Vec<f64>,
+    /// Number of segments
+    count: i32,
+    closed: bool,
 }
 
 impl Way {
+    /// Closed way through the points
     pub fn new(points0: &[[f64; 2]], scale: f64) -> Way {
+        Way::build(points0, scale, true)
+    }
+
+    /// Way from the first point to the last one
+    pub fn open(points0: &[[f64; 2]], scale: f64) -> Way {
+        Way::build(points0, scale, false)
+    }
+
+    fn build(points0: &[[f64; 2]], scale: f64, closed: bool) -> Way {
         let mut points = Vec::new();
         let mut segment_len = Vec::new();
         let len = points0.len();
@@ -157,12 +405,102 @@ impl Way {
         for i in 0..len-1 {
             segment_len.push((points[i+1] - points[i]).norm());
         }
-        segment_len.push((points[len - 1] - points[0]).norm());
+        if closed {
+            segment_len.push((points[len - 1] - points[0]).norm());
+        }
         Way {
-            count: len as i32,
+            count: segment_len.len() as i32,
+            half_widths: vec![0.0; points.len()],
             points: points,
-            segment_len: segment_len
+            segment_len: segment_len,
+            closed: closed
+        }
+    }
+
+    /// Sets the distances from the points to the walls
+    pub fn with_half_widths(mut self, half_widths: Vec<f64>) -> Way {
+        assert_eq!(half_widths.len(), self.points.len());
+        self.half_widths = half_widths;
+        self
+    }
+
+    /// Distance from the way point to the walls, between those
+    /// of the ends of its segment; zero if not known
+    pub fn half_width(&self, wp: &WayPoint) -> f64 {
+        let i = wp.segment as usize;
+        let t = (wp.offset / self.segment_len[i]).max(0.0).min(1.0);
+        (1.0 - t) * self.half_widths[i] + t * self.half_widths[self.next(wp.segment) as usize]
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Total length of the way, a lap for a closed one
+    pub fn length(&self) -> f64 {
+        self.segment_len.iter().fold(0.0, |acc, l| acc + l)
+    }
+
+    // Index of the end point of the segment; the next segment
+    // unless the segment is the last one of an open way
+    fn next(&self, segment: i32) -> i32 {
+        if segment + 1 == self.count && self.closed { 0 } else { segment + 1 }
+    }
+
+    /// Way point at the given distance from the beginning of the way.
+    /// The distance wraps around a closed way and is clamped
+    /// to the ends of an open one.
+    pub fn at_distance(&self, distance: f64) -> WayPoint {
+        let length = self.length();
+        let mut d = if self.closed { distance % length } else { distance.min(length) };
+        if d < 0.0 {
+            d = if self.closed { d + length } else { 0.0 };
+        }
+        let mut segment = 0;
+        while segment + 1 < self.count && d > self.segment_len[segment as usize] {
+            d -= self.segment_len[segment as usize];
+            segment += 1;
         }
+        WayPoint { segment: segment, offset: d }
+    }
+
+    /// Distance of the way point from the beginning of the way
+    pub fn distance(&self, wp: &WayPoint) -> f64 {
+        self.segment_len[..wp.segment as usize].iter().fold(wp.offset, |acc, l| acc + l)
+    }
+
+    /// Position of the way point
+    pub fn point(&self, wp: &WayPoint) -> Pt {
+        self.points[wp.segment as usize] + wp.offset * self.direction(wp)
+    }
+
+    /// Unit direction of the segment of the way point
+    pub fn direction(&self, wp: &WayPoint) -> Pt {
+        let a = self.points[wp.segment as usize];
+        let b = self.points[self.next(wp.segment) as usize];
+        1.0 / self.segment_len[wp.segment as usize] * (b - a)
+    }
+
+    /// Sum of the signed turns of the way (counterclockwise is positive)
+    /// at the points within `distance` ahead of the way point
+    pub fn turn_ahead(&self, wp: &WayPoint, distance: f64) -> f64 {
+        let mut turn = 0.0;
+        let mut segment = wp.segment;
+        let mut left = distance - (self.segment_len[segment as usize] - wp.offset);
+        let mut i = 0;
+        while left > 0.0 && i < self.count {
+            let next = self.next(segment);
+            if next == self.count {
+                break;
+            }
+            let d0 = self.direction(&WayPoint { segment: segment, offset: 0.0 });
+            let d1 = self.direction(&WayPoint { segment: next, offset: 0.0 });
+            turn += (d0.x * d1.y - d0.y * d1.x).atan2(d0.x * d1.x + d0.y * d1.y);
+            left -= self.segment_len[next as usize];
+            segment = next;
+            i += 1;
+        }
+        turn
     }
 
     pub fn where_is(&self, p: Pt) -> WayPoint {
@@ -176,7 +514,7 @@ impl Way {
         let mut min_pr = Projection {distance: 1.0e20, wp: WayPoint::zero()};
         for i in 0..self.count {
             let a = self.points[i as usize];
-            let b = self.points[if (i+1) == self.count { 0 } else { i+1 } as usize];
+            let b = self.points[self.next(i) as usize];
             let pr = Projection::project(a, b, p, i as i32);
             if pr.distance < min_pr.distance {
                 min_pr = pr;
@@ -185,26 +523,200 @@ impl Way {
         return min_pr.wp;
     }
 
+    /// Nearest way point to `p` among the segments within `reach`
+    /// along the way from `hint`, the way point of `p` a step ago.
+    /// Parts of the way that come close to each other but are far
+    /// apart along it do not get mixed up.
+    pub fn where_is_near(&self, p: Pt, hint: &WayPoint, reach: f64) -> WayPoint {
+        let mut segments = vec![hint.segment];
+        let mut segment = hint.segment;
+        let mut ahead = self.segment_len[segment as usize] - hint.offset;
+        while ahead < reach && segments.len() < self.count as usize {
+            segment = self.next(segment);
+            if segment == self.count || segment == hint.segment {
+                break;
+            }
+            segments.push(segment);
+            ahead += self.segment_len[segment as usize];
+        }
+        let mut segment = hint.segment;
+        let mut behind = hint.offset;
+        while behind < reach && segments.len() < self.count as usize {
+            if segment == 0 && !self.closed {
+                break;
+            }
+            segment = if segment == 0 { self.count - 1 } else { segment - 1 };
+            if segments.contains(&segment) {
+                break;
+            }
+            segments.push(segment);
+            behind += self.segment_len[segment as usize];
+        }
+        let mut min_pr = Projection {distance: 1.0e20, wp: *hint};
+        for &i in segments.iter() {
+            let pr = Projection::project(self.points[i as usize], self.points[self.next(i) as usize], p, i);
+            if pr.distance < min_pr.distance {
+                min_pr = pr;
+            }
+        }
+        min_pr.wp
+    }
+
+    /// Signed distance along the way from `old` to `new`;
+    /// round a closed way it is the shorter one
     pub fn offset(&self, old: &WayPoint, new: &WayPoint) -> f64 {
         if new.segment == old.segment {
             //println!("1");
             //println!("oo: {}, no: {}", old.offset, new.offset);
             new.offset - old.offset
         } else if (new.segment - old.segment == 1) // going to the next segment
-               || ((old.segment == self.count - 1) && (new.segment == 0)) {
+               || (self.closed && (old.segment == self.count - 1) && (new.segment == 0)) {
             //println!("2");
             //println!("oo: {}, no: {}", old.offset, new.offset);
             self.segment_len[old.segment as usize] - old.offset + new.offset
         } else if (old.segment - new.segment == 1) // going to the previous segment
-               || ((new.segment == self.count - 1) && (old.segment == 0)) {
+               || (self.closed && (new.segment == self.count - 1) && (old.segment == 0)) {
             //println!("3");
             //println!("os: {}, ns: {}", old.segment, new.segment);
             //println!("oo: {}, no: {}", old.offset, new.offset);
             //println!("sl: {}", self.segment_len[new.segment as usize]);
-            self.segment_len[new.segment as usize] - new.offset + old.offset
+            -(self.segment_len[new.segment as usize] - new.offset + old.offset)
         } else {
-            //println!("4");
-            panic!("Should not be here!")
+            // Farther apart: the shorter way round a closed way
+            let d = self.distance(new) - self.distance(old);
+            let length = self.length();
+            if !self.closed {
+                d
+            } else if d > 0.5 * length {
+                d - length
+            } else if d < -0.5 * length {
+                d + length
+            } else {
+                d
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path;
+    use trackcheck;
+
+    #[test]
+    fn clover_file_matches_built_in_track() {
+        let data = TrackData::load(path::Path::new("tracks/clover.json")).unwrap();
+        let walls = data.walls();
+        let built_in = clover(4.0, 10.0);
+        assert_eq!(format!("{:?}", walls), format!("{:?}", built_in));
+        assert_eq!(data.way().length(), Way::new(&clover_data, 10.0).length());
+        assert_eq!(data.spawn_poses(), vec![[-110.0, 0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn open_track_ends_are_walled() {
+        let data = TrackData::load(path::Path::new("tracks/hairpin.json")).unwrap();
+        let walls = data.walls();
+        // Both sides and the ends in one path, and the obstacle
+        assert_eq!(walls.paths.len(), 2);
+        assert_eq!(walls.paths[0].sects.len(), 2 * data.points.len());
+
+        let way = data.way();
+        assert!(!way.is_closed());
+        let length = way.length();
+        let end = way.at_distance(length + 100.0);
+        assert!((way.point(&end) - Pt::new(0.0, 120.0)).norm() < 1e-9);
+        let start = way.at_distance(-5.0);
+        assert!(way.point(&start).norm() < 1e-9);
+        // Beyond the end the car is at the end, not at the start
+        let wp = way.where_is(Pt::new(-20.0, 115.0));
+        assert!((way.point(&wp) - Pt::new(0.0, 120.0)).norm() < 1e-9);
+        let a = way.at_distance(75.0);
+        let b = way.at_distance(85.0);
+        assert!((way.offset(&a, &b) - 10.0).abs() < 1e-9);
+    }
+
+    // Distance from `p` to the line through `a` and `b`
+    fn line_distance(p: Pt, a: Pt, b: Pt) -> f64 {
+        let (u, v) = (b - a, p - a);
+        (u.x * v.y - u.y * v.x).abs() / u.norm()
+    }
+
+    #[test]
+    fn mitered_and_rounded_walls_keep_the_width() {
+        let points = [Pt::new(0.0, 0.0), Pt::new(10.0, 0.0), Pt::new(10.0, 10.0), Pt::new(0.0, 10.0)];
+        let widths = [1.0, 2.0, 1.0, 2.0];
+        for &join in [Join::Miter, Join::Round].iter() {
+            let (left, right) = offset(&points, &widths, true, join, DEFAULT_MITER_LIMIT);
+            for i in 0..points.len() {
+                let (x0, x1, x2) = (points[(i + 3) % 4], points[i], points[(i + 1) % 4]);
+                // The inner corners are mitered
+                let p = left.joint(i)[0];
+                assert!((line_distance(p, x0, x1) - widths[i]).abs() < 1e-9);
+                assert!((line_distance(p, x1, x2) - widths[i]).abs() < 1e-9);
+                for p in right.joint(i).iter() {
+                    if join == Join::Round {
+                        assert!(((*p - x1).norm() - widths[i]).abs() < 1e-9);
+                    } else {
+                        assert!((line_distance(*p, x0, x1) - widths[i]).abs() < 1e-9);
+                        assert!((line_distance(*p, x1, x2) - widths[i]).abs() < 1e-9);
+                    }
+                }
+            }
+            // Outer corners are rounded in steps of a quarter of the turn
+            assert_eq!(left.points.len(), 4);
+            assert_eq!(right.points.len(), if join == Join::Round { 20 } else { 4 });
+        }
+        let (_, right) = offset(&points, &widths, true, Join::Round, DEFAULT_MITER_LIMIT);
+        assert_eq!(right.owner(6), (1, true));
+        assert_eq!(right.owner(9), (1, false));
+        // Past the limit the corner is cut off
+        let (_, right) = offset(&points, &widths, true, Join::Miter, 1.2);
+        assert_eq!(right.points.len(), 8);
+    }
+
+    #[test]
+    fn chicane_is_narrower_than_the_straights() {
+        let data = TrackData::load(path::Path::new("tracks/chicane.json")).unwrap();
+        assert_eq!(trackcheck::validate(&data, 2.0 * 3.0 - 1e-6), vec![]);
+        match trackcheck::validate(&data, 7.0).first() {
+            Some(&trackcheck::Problem::Narrow { point, .. }) => assert_eq!(point, 2),
+            p => panic!("{:?}", p)
+        }
+    }
+
+    #[test]
+    fn far_way_points_are_apart_along_the_way() {
+        let square = Way::new(&[[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]], 1.0);
+        let (a, b) = (square.at_distance(390.0), square.at_distance(10.0));
+        assert!((square.offset(&a, &b) - 20.0).abs() < 1e-9);
+        assert!((square.offset(&b, &a) + 20.0).abs() < 1e-9);
+        let c = square.at_distance(250.0);
+        assert!((square.offset(&b, &c) + 160.0).abs() < 1e-9);
+        assert!((square.offset(&c, &b) - 160.0).abs() < 1e-9);
+
+        let u = Way::open(&[[0.0, 0.0], [100.0, 0.0], [100.0, 10.0], [0.0, 10.0]], 1.0);
+        let (a, b) = (u.at_distance(20.0), u.at_distance(160.0));
+        assert!((u.offset(&a, &b) - 140.0).abs() < 1e-9);
+        assert!((u.offset(&b, &a) + 140.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hinted_way_point_stays_on_the_near_part() {
+        // The way comes back 10 apart from itself
+        let u = Way::open(&[[0.0, 0.0], [100.0, 0.0], [100.0, 10.0], [0.0, 10.0]], 1.0);
+        let p = Pt::new(50.0, 4.0);
+        assert!((u.distance(&u.where_is(p)) - 50.0).abs() < 1e-9);
+        let hint = u.at_distance(162.0);
+        let wp = u.where_is_near(p, &hint, 30.0);
+        assert!((u.distance(&wp) - 160.0).abs() < 1e-9);
+        // Far enough to reach the other part
+        let wp = u.where_is_near(p, &hint, 200.0);
+        assert!((u.distance(&wp) - 50.0).abs() < 1e-9);
+        // Round a closed way from the last segment to the first one
+        let square = Way::new(&[[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]], 1.0);
+        let wp = square.where_is_near(Pt::new(5.0, 1.0), &square.at_distance(395.0), 20.0);
+        assert!((square.distance(&wp) - 5.0).abs() < 1e-9);
+    }
+}
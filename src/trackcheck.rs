@@ -0,0 +1,218 @@
+//! Checks of the walls built along a center line. Problems are
+//! given by the segments of the center line: segment `i` joins the
+//! points `i` and `i + 1`, as segment `i` of the way does, and so
+//! does the wall along it.
+use std::fmt;
+use geom::{Pt, segments_cross, point_segment_distance};
+use track::{TrackData, Wall};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// Points `index` and `index + 1` of the center line are the same
+    DuplicatePoint { index: usize },
+    /// The walls along two segments cross each other
+    WallCrossesItself { side: Side, segments: (usize, usize) },
+    /// The wall along a segment runs against it, as the inner wall
+    /// of a corner too sharp for the width does
+    WallReversed { side: Side, segment: usize },
+    /// The left wall along segment `left` crosses the right one along `right`
+    WallsCross { left: usize, right: usize },
+    /// The walls at the point are closer than the width of the car
+    Narrow { point: usize, width: f64 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::DuplicatePoint { index } =>
+                write!(f, "points {} and {} are the same", index, index + 1),
+            Problem::WallCrossesItself { side, segments: (i, j) } =>
+                write!(f, "{:?} wall segments {} and {} cross", side, i, j),
+            Problem::WallReversed { side, segment } =>
+                write!(f, "{:?} wall segment {} runs backwards", side, segment),
+            Problem::WallsCross { left, right } =>
+                write!(f, "left wall segment {} crosses right wall segment {}", left, right),
+            Problem::Narrow { point, width } =>
+                write!(f, "track is {:.2} wide at point {}", width, point),
+        }
+    }
+}
+
+/// Problems of the walls of the track for a car of the given width.
+/// If the center line has duplicate points, only they are reported:
+/// the walls cannot be built.
+pub fn validate(data: &TrackData, car_width: f64) -> Vec<Problem> {
+    let points: Vec<Pt> = data.points.iter()
+        .map(|p| data.scale * Pt::new(p[0], p[1]))
+        .collect();
+    let n = points.len();
+    let nsegs = if data.closed { n } else { n - 1 };
+    let next = |i: usize| (i + 1) % n;
+
+    let duplicates: Vec<Problem> = (0..nsegs)
+        .filter(|&i| (points[next(i)] - points[i]).norm() < 1e-9)
+        .map(|i| Problem::DuplicatePoint { index: i })
+        .collect();
+    if !duplicates.is_empty() {
+        return duplicates;
+    }
+
+    let mut problems = Vec::new();
+    let (left, right) = data.sides();
+    for &(side, wall) in [(Side::Left, &left), (Side::Right, &right)].iter() {
+        let segs = segments(wall, data.closed);
+        for &(k, i, joint) in segs.iter() {
+            let (a, b) = ends(wall, k);
+            let d = points[next(i)] - points[i];
+            let w = b - a;
+            if !joint && d.x * w.x + d.y * w.y < 0.0 {
+                problems.push(Problem::WallReversed { side: side, segment: i });
+            }
+        }
+        let m = wall.points.len();
+        for (x, &(k, i, _)) in segs.iter().enumerate() {
+            for &(l, j, _) in segs[x + 1..].iter() {
+                // Segments of a wall next to each other share a point
+                if l == k + 1 || (data.closed && k == 0 && l == m - 1) {
+                    continue;
+                }
+                let (a, b) = ends(wall, k);
+                let (c, d) = ends(wall, l);
+                let problem = Problem::WallCrossesItself { side: side, segments: (i, j) };
+                if segments_cross(a, b, c, d) && !problems.contains(&problem) {
+                    problems.push(problem);
+                }
+            }
+        }
+    }
+    let (lsegs, rsegs) = (segments(&left, data.closed), segments(&right, data.closed));
+    for &(k, i, _) in lsegs.iter() {
+        for &(l, j, _) in rsegs.iter() {
+            let (a, b) = ends(&left, k);
+            let (c, d) = ends(&right, l);
+            let problem = Problem::WallsCross { left: i, right: j };
+            if segments_cross(a, b, c, d) && !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+    }
+    // Width at a point: from each of its wall points to the
+    // other wall next to it
+    for i in 0..n {
+        let near = |&&(_, j, joint): &&(usize, usize, bool)| {
+            j == i || (!joint && next(j) == i)
+        };
+        let width = |from: &Wall, to: &Wall, segs: &[(usize, usize, bool)]| {
+            from.joint(i).iter().fold(::std::f64::INFINITY, |w, &p| {
+                segs.iter().filter(&near).fold(w, |w, &(l, _, _)| {
+                    let (a, b) = ends(to, l);
+                    w.min(point_segment_distance(p, a, b))
+                })
+            })
+        };
+        let w = width(&left, &right, &rsegs).min(width(&right, &left, &lsegs));
+        if w < car_width {
+            problems.push(Problem::Narrow { point: i, width: w });
+        }
+    }
+    problems
+}
+
+// Segments of the wall: index, segment of the center line, and
+// whether it is a part of the joint at the start of that segment
+fn segments(wall: &Wall, closed: bool) -> Vec<(usize, usize, bool)> {
+    let m = wall.points.len();
+    let count = if closed { m } else { m - 1 };
+    (0..count).map(|k| {
+        let (i, joint) = wall.owner(k);
+        (k, i, joint)
+    }).collect()
+}
+
+fn ends(wall: &Wall, k: usize) -> (Pt, Pt) {
+    (wall.points[k], wall.points[(k + 1) % wall.points.len()])
+}
+
+/// Error listing the problems, if there are any
+pub fn check(data: &TrackData, car_width: f64) -> Result<(), String> {
+    let problems = validate(data, car_width);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        let list: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+        Err(format!("Bad track: {}", list.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use track::TrackData;
+
+    fn track(points: Vec<[f64; 2]>, half_width: f64, closed: bool) -> TrackData {
+        TrackData {
+            points: points,
+            scale: 1.0,
+            half_width: half_width,
+            widths: None,
+            join: None,
+            miter_limit: None,
+            closed: closed,
+            spawns: None,
+            obstacles: None,
+        }
+    }
+
+    #[test]
+    fn clover_is_valid() {
+        assert_eq!(validate(&TrackData::clover(4.0, 10.0), 1.6), vec![]);
+    }
+
+    #[test]
+    fn duplicate_points_are_found() {
+        let t = track(vec![[0.0, 0.0], [10.0, 0.0], [10.0, 0.0], [10.0, 10.0]], 1.0, true);
+        assert_eq!(validate(&t, 1.0), vec![Problem::DuplicatePoint { index: 1 }]);
+    }
+
+    #[test]
+    fn tight_corners_reverse_the_inner_wall() {
+        // Ends of the loop are shorter than the width
+        let t = track(vec![[0.0, 0.0], [20.0, 0.0], [20.0, 2.0], [0.0, 2.0]], 3.0, true);
+        let problems = validate(&t, 0.0);
+        assert_eq!(problems, vec![Problem::WallReversed { side: Side::Left, segment: 1 },
+                                  Problem::WallReversed { side: Side::Left, segment: 3 }]);
+    }
+
+    #[test]
+    fn crossing_center_line_crosses_the_walls() {
+        let t = track(vec![[0.0, 0.0], [50.0, 50.0], [50.0, 0.0], [0.0, 50.0]], 2.0, true);
+        let problems = validate(&t, 0.0);
+        assert!(problems.contains(&Problem::WallCrossesItself { side: Side::Left, segments: (0, 2) }),
+                "{:?}", problems);
+        assert!(problems.contains(&Problem::WallCrossesItself { side: Side::Right, segments: (0, 2) }),
+                "{:?}", problems);
+        assert!(problems.contains(&Problem::WallsCross { left: 0, right: 2 }), "{:?}", problems);
+    }
+
+    #[test]
+    fn narrow_track_is_found() {
+        // The bisector rule narrows square corners to sqrt(2) half widths
+        let t = track(vec![[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]], 1.0, true);
+        assert_eq!(validate(&t, 1.4), vec![]);
+        let problems = validate(&t, 1.6);
+        assert_eq!(problems.len(), 4);
+        match problems[0] {
+            Problem::Narrow { point, width } => {
+                assert_eq!(point, 0);
+                assert!((width - 2.0f64.sqrt()).abs() < 1e-9);
+            },
+            ref p => panic!("{:?}", p)
+        }
+    }
+}
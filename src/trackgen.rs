@@ -0,0 +1,214 @@
+//! Random closed tracks. The center line is a polygon with randomly
+//! perturbed corners around a circle, rounded by corner cutting, and
+//! drawn again until it keeps to the limits of curvature, clearance
+//! and length.
+use std::f64::consts::PI;
+use rand::{Rng, StdRng};
+use geom::{Pt, segments_distance};
+use track::TrackData;
+use trackcheck;
+
+/// Lengths are in world units
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TrackGenConfig {
+    /// Corners of the polygon before rounding
+    pub corners: usize,
+    /// Mean distance of the corners from the center
+    pub radius: f64,
+    /// Relative spread of the distances of the corners, 0..1
+    pub irregularity: f64,
+    /// Spread of the angles of the corners in parts of the
+    /// angle between them, 0..1
+    pub angle_jitter: f64,
+    /// Rounds of corner cutting; each doubles the points
+    pub smoothing: usize,
+    /// Smallest radius of a turn of the center line
+    pub min_radius: f64,
+    /// Smallest distance between parts of the center line that are
+    /// not next to each other; more than twice the half width keeps
+    /// the walls apart
+    pub min_clearance: f64,
+    pub min_length: f64,
+    pub max_length: f64,
+    /// Center lines drawn before giving up
+    pub attempts: usize,
+    /// Whether every world gets a track of its own. The tracks are
+    /// generated once, when the experiment is set up, and kept
+    /// for all the episodes.
+    pub per_world: bool,
+}
+
+impl TrackGenConfig {
+    pub fn default() -> TrackGenConfig {
+        TrackGenConfig {
+            corners: 12,
+            radius: 100.0,
+            irregularity: 0.4,
+            angle_jitter: 0.5,
+            smoothing: 3,
+            min_radius: 10.0,
+            min_clearance: 16.0,
+            min_length: 400.0,
+            max_length: 1500.0,
+            attempts: 1000,
+            per_world: false,
+        }
+    }
+}
+
+/// Closed track of the given half width drawn from `rng`, starting
+/// at the first point of the center line and heading to the second.
+/// The walls are kept wider apart than the car.
+pub fn generate(config: &TrackGenConfig, half_width: f64, car_width: f64,
+                rng: &mut StdRng) -> Result<TrackData, String> {
+    if config.corners < 3 {
+        return Err("A track needs at least 3 corners".to_string());
+    }
+    for _ in 0..config.attempts {
+        let points = smooth(&corners(config, rng), config.smoothing);
+        if check(config, &points).is_err() {
+            continue;
+        }
+        let course = points[1] - points[0];
+        let data = TrackData {
+            points: points.iter().map(|p| [p.x, p.y]).collect(),
+            scale: 1.0,
+            half_width: half_width,
+            widths: None,
+            join: None,
+            miter_limit: None,
+            closed: true,
+            spawns: Some(vec![[points[0].x, points[0].y, course.x, course.y]]),
+            obstacles: None,
+        };
+        // The limits keep the walls apart only if they suit the
+        // half width, and the car has to fit between them
+        if trackcheck::validate(&data, car_width).is_empty() {
+            return Ok(data);
+        }
+    }
+    Err(format!("No track within the limits in {} attempts", config.attempts))
+}
+
+// Corners in the order of their angles, so the polygon
+// does not cross itself
+fn corners(config: &TrackGenConfig, rng: &mut StdRng) -> Vec<Pt> {
+    let n = config.corners;
+    let step = 2.0 * PI / n as f64;
+    (0..n).map(|i| {
+        let jitter = config.angle_jitter.min(1.0) * 0.5 * step;
+        let angle = i as f64 * step + if jitter > 0.0 { rng.gen_range(-jitter, jitter) } else { 0.0 };
+        let spread = config.irregularity.min(0.99);
+        let r = config.radius * if spread > 0.0 { rng.gen_range(1.0 - spread, 1.0 + spread) } else { 1.0 };
+        Pt::new(r * angle.cos(), r * angle.sin())
+    }).collect()
+}
+
+// Chaikin's corner cutting of a closed polygon
+fn smooth(points: &[Pt], rounds: usize) -> Vec<Pt> {
+    let mut ps = points.to_vec();
+    for _ in 0..rounds {
+        let n = ps.len();
+        let mut next = Vec::with_capacity(2 * n);
+        for i in 0..n {
+            let a = ps[i];
+            let b = ps[(i + 1) % n];
+            next.push(0.75 * a + 0.25 * b);
+            next.push(0.25 * a + 0.75 * b);
+        }
+        ps = next;
+    }
+    ps
+}
+
+/// Checks a closed center line against the limits of the generator
+pub fn check(config: &TrackGenConfig, points: &[Pt]) -> Result<(), String> {
+    let n = points.len();
+    let lens: Vec<f64> = (0..n).map(|i| (points[(i + 1) % n] - points[i]).norm()).collect();
+    let length = lens.iter().fold(0.0, |s, l| s + l);
+    if length < config.min_length || length > config.max_length {
+        return Err(format!("Length {:.1} is out of {}..{}",
+                           length, config.min_length, config.max_length));
+    }
+    for i in 0..n {
+        let prev = (i + n - 1) % n;
+        let d0 = points[i] - points[prev];
+        let d1 = points[(i + 1) % n] - points[i];
+        let turn = (d0.x * d1.y - d0.y * d1.x).atan2(d0.x * d1.x + d0.y * d1.y).abs();
+        if turn > 1e-9 {
+            let radius = 0.5 * (lens[prev] + lens[i]) / turn;
+            if radius < config.min_radius {
+                return Err(format!("Turn of radius {:.1} at point {}", radius, i));
+            }
+        }
+    }
+    // Parts of a U-turn as wide as the clearance are this far apart
+    // along the line; closer parts are limited by the curvature
+    let near = 0.5 * PI * config.min_clearance;
+    let mut starts = Vec::with_capacity(n);
+    let mut s = 0.0;
+    for l in lens.iter() {
+        starts.push(s);
+        s += *l;
+    }
+    for i in 0..n {
+        for j in i + 1..n {
+            let along = starts[j] - (starts[i] + lens[i]);
+            let around = starts[i] + length - (starts[j] + lens[j]);
+            if along < near || around < near {
+                continue;
+            }
+            let d = segments_distance(points[i], points[(i + 1) % n],
+                                      points[j], points[(j + 1) % n]);
+            if d < config.min_clearance {
+                return Err(format!("Segments {} and {} are {:.1} apart", i, j, d));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{SeedableRng, StdRng};
+    use geom::Pt;
+
+    #[test]
+    fn generated_tracks_keep_to_the_limits() {
+        let config = TrackGenConfig::default();
+        for seed in 0..10 {
+            let mut rng: StdRng = SeedableRng::from_seed(&[seed][..]);
+            let data = generate(&config, 4.0, 1.6, &mut rng).unwrap();
+            let points: Vec<Pt> = data.points.iter().map(|p| Pt::new(p[0], p[1])).collect();
+            assert!(check(&config, &points).is_ok());
+            assert_eq!(trackcheck::validate(&data, 1.6), vec![]);
+            let way = data.way();
+            assert!(way.length() >= config.min_length && way.length() <= config.max_length);
+        }
+        let mut a: StdRng = SeedableRng::from_seed(&[7usize][..]);
+        let mut b: StdRng = SeedableRng::from_seed(&[7usize][..]);
+        assert_eq!(generate(&config, 4.0, 1.6, &mut a).unwrap().points,
+                   generate(&config, 4.0, 1.6, &mut b).unwrap().points);
+    }
+
+    #[test]
+    fn close_parts_are_refused() {
+        let mut config = TrackGenConfig::default();
+        config.min_length = 0.0;
+        config.min_radius = 0.0;
+        // A thin rectangle: the long sides are 10 apart
+        let corners = [Pt::new(0.0, 0.0), Pt::new(100.0, 0.0),
+                       Pt::new(100.0, 10.0), Pt::new(0.0, 10.0)];
+        let mut points = Vec::new();
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            let n = ((b - a).norm() / 5.0) as usize;
+            points.extend((0..n).map(|k| a + k as f64 / n as f64 * (b - a)));
+        }
+        config.min_clearance = 5.0;
+        assert!(check(&config, &points).is_ok());
+        config.min_clearance = 12.0;
+        assert!(check(&config, &points).is_err());
+    }
+}
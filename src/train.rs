@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+use std::path;
+use polygon::{Polygon, dir_of_workspace};
+use config::Config;
+use checkpoint::{self, Checkpoints, Manifest, read_json};
+use eval::EvalOptions;
+use metrics::{self, MetricsLog};
+
+/// Settings of a headless training run. The run stops when
+/// either of the limits is reached; without limits it runs forever.
+pub struct TrainOptions {
+    /// Cycles of this run, not counting those of the resumed checkpoint
+    pub cycles: Option<u64>,
+    pub duration: Option<Duration>,
+    /// Save a checkpoint every that many cycles (0 - only at the end)
+    pub checkpoint_cycles: u64,
+    /// Periodic checkpoints to keep (0 - all)
+    pub keep_checkpoints: usize,
+    /// Greedy episodes evaluated at each checkpoint to pick the best
+    /// one (0 - use the returns of the training episodes instead)
+    pub eval_episodes: usize,
+    /// Step limit of the evaluation episodes if the episode
+    /// configuration has none
+    pub eval_max_steps: u32,
+    /// Print progress every that many cycles
+    pub report_cycles: u64,
+    /// Also log the metrics of every report to the workspace
+    pub metrics: Option<metrics::Format>,
+    /// Continue from the learner saved in the workspace
+    pub resume: bool,
+    /// Continue from this checkpoint directory instead
+    pub resume_from: Option<path::PathBuf>,
+    /// Experiment configuration file. Without it a resumed run
+    /// uses the configuration of the checkpoint, a new one
+    /// uses the default configuration.
+    pub config: Option<path::PathBuf>,
+    /// Override the threads and the seed of the configuration
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+impl TrainOptions {
+    pub fn new() -> TrainOptions {
+        TrainOptions {
+            cycles: None,
+            duration: None,
+            checkpoint_cycles: 100000,
+            keep_checkpoints: 5,
+            eval_episodes: 10,
+            eval_max_steps: 5000,
+            report_cycles: 10000,
+            metrics: Some(metrics::Format::Csv),
+            resume: false,
+            resume_from: None,
+            config: None,
+            threads: None,
+            seed: None,
+        }
+    }
+}
+
+const LOOP_CYCLES: u64 = 100;
+/// Training episodes averaged for the reports
+const REPORT_EPISODES: usize = 100;
+
+// Return of the greedy policy, always on the same spawn poses
+fn evaluate(pg: &Polygon, episodes: usize, max_steps: u32) -> Option<f64> {
+    if episodes == 0 {
+        return pg.episodes.mean(REPORT_EPISODES).map(|(ret, _)| ret);
+    }
+    let mut opts = EvalOptions::new(episodes, pg.config.seed);
+    if pg.config.episode.termination.max_steps.is_none() {
+        opts.max_steps = Some(max_steps);
+    }
+    match pg.evaluate(&opts) {
+        Ok(report) => {
+            println!("Evaluation: {}", report.summary());
+            Some(report.mean_return)
+        },
+        Err(e) => {
+            println!("Evaluation failed: {}", e);
+            None
+        }
+    }
+}
+
+// Saves a periodic checkpoint, and the best one if the
+// evaluation return has improved
+fn checkpoint(pg: &mut Polygon, checkpoints: &Checkpoints, ws_dir: &path::Path,
+              opts: &TrainOptions) {
+    if let Some(ret) = evaluate(pg, opts.eval_episodes, opts.eval_max_steps) {
+        if pg.progress.best_return.map_or(true, |b| ret > b) {
+            pg.progress.best_return = Some(ret);
+            match pg.save_checkpoint(&ws_dir.join(checkpoint::BEST_DIR)) {
+                Ok(()) => println!("Best checkpoint saved, return: {:.2}", ret),
+                Err(e) => println!("Best checkpoint failed: {}", e)
+            }
+        }
+    }
+    let cycles = pg.progress.cycles;
+    match pg.save_checkpoint(&checkpoints.path(cycles)).and_then(|_| checkpoints.rotate()) {
+        Ok(()) => println!("Checkpoint saved at {} cycles", cycles),
+        Err(e) => println!("Checkpoint at {} cycles failed: {}", cycles, e)
+    }
+}
+
+pub fn run(workspace: &str, opts: &TrainOptions) {
+    let ws_dir = dir_of_workspace(workspace).unwrap_or_else(|e| panic!("{}", e));
+    let resume_dir = match opts.resume_from {
+        Some(ref dir) => Some(dir.clone()),
+        None if opts.resume => Some(ws_dir.clone()),
+        None => None
+    };
+    let mut config = match (&opts.config, &resume_dir) {
+        (&Some(ref filename), _) => Config::load(filename),
+        (&None, &Some(ref dir)) => read_json::<Manifest>(&dir.join(checkpoint::MANIFEST_FILE))
+            .map(|m| m.config),
+        (&None, &None) => Ok(Config::default())
+    }.unwrap_or_else(|e| panic!("{}", e));
+    if let Some(threads) = opts.threads {
+        config.threads = threads;
+    }
+    if let Some(seed) = opts.seed {
+        config.seed = seed;
+    }
+    let mut pg = Polygon::from_config(ws_dir.clone(), &config)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(ref dir) = resume_dir {
+        pg.load_checkpoint(dir).unwrap_or_else(|e| panic!("Cannot resume: {}", e));
+        println!("Resumed from {:?} at {} cycles", dir, pg.progress.cycles);
+    }
+    println!("Training in {:?}", ws_dir);
+    let checkpoints = Checkpoints::new(&ws_dir, opts.keep_checkpoints);
+    let mut metrics_log = opts.metrics.map(|f| MetricsLog::open(&ws_dir, f)
+                                           .unwrap_or_else(|e| panic!("{}", e)));
+
+    let start = Instant::now();
+    let mut all_cycles: u64 = 0;
+    let mut ar = 0.0;
+    let mut ar_cycles: u64 = 0;
+    loop {
+        let n = match opts.cycles {
+            Some(c) if c - all_cycles < LOOP_CYCLES => c - all_cycles,
+            _ => LOOP_CYCLES
+        };
+        let before = pg.progress.cycles;
+        ar += pg.run(n as u32);
+        all_cycles += n;
+        ar_cycles += n;
+
+        let finished = opts.cycles.map_or(false, |c| all_cycles >= c)
+            || opts.duration.map_or(false, |d| start.elapsed() >= d);
+
+        // Nothing to report without cycles run, as with --cycles 0
+        if ar_cycles > 0 && (ar_cycles >= opts.report_cycles || finished) {
+            let elapsed = start.elapsed();
+            let avg = ar / ar_cycles as f64;
+            let cycles = pg.progress.cycles;
+            pg.progress.reward_history.push((cycles, avg));
+            let mut line = format!("cycles: {}, time: {}s, avg reward: {:.4}, sigma: {:.4}",
+                                   cycles, elapsed.as_secs(), avg,
+                                   pg.learner.sigma());
+            if let Some((ret, len)) = pg.episodes.mean(REPORT_EPISODES) {
+                line.push_str(&format!(", episodes: {}, avg return: {:.2}, avg length: {:.1}",
+                                       pg.episodes.count, ret, len));
+            }
+            println!("{}", line);
+            let row = pg.metrics();
+            if let Some(ref mut log) = metrics_log {
+                if let Err(e) = log.write(&row) {
+                    println!("{}", e);
+                }
+            }
+            ar = 0.0;
+            ar_cycles = 0;
+        }
+
+        let every = opts.checkpoint_cycles;
+        if every > 0 && pg.progress.cycles / every > before / every {
+            checkpoint(&mut pg, &checkpoints, &ws_dir, opts);
+        }
+        if finished {
+            break;
+        }
+    }
+    pg.save().unwrap_or_else(|e| panic!("{}", e));
+    println!("Saved to {:?} after {} cycles", ws_dir, pg.progress.cycles);
+}
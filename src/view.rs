@@ -6,12 +6,14 @@ use std::thread::sleep;
 use std::time::Duration;
 use geom::{Figure, Path, Pt};
 use track;
-use polygon::Polygon;
+use polygon::{Polygon, dir_of_workspace, track_per_world};
 use polyshape::{Polyshape, Polyshapable, PolyshapeStyle};
-use std::fs;
-use std::path;
+use std::env;
 use std::ops::Deref;
 use plot::{Plot};
+use metrics::{MetricsLog, Format};
+use recording::Recording;
+use std::path;
 
 #[derive(Clone, Copy)]
 pub struct TriangleShape;
@@ -96,14 +98,7 @@ impl View {
 //    pub shapes: Vec<CustomShape>
 //}
 
-fn dir_of_workspace(workspace: &str) -> path::PathBuf {
-    let dir = path::Path::new("./workspaces/").join(workspace);
-    fs::create_dir_all(dir.as_path());
-    dir
-}
-
-pub fn run(workspace: &str) {
-    let ws_dir = dir_of_workspace(workspace);
+fn open_window() -> RenderWindow {
     let mut settings = ContextSettings::default();
     settings.0.antialiasing_level = 16;
     let mut window = RenderWindow::new(VideoMode::new_init(1820, 970, 32),
@@ -115,6 +110,19 @@ pub fn run(workspace: &str) {
     window.set_position(&Vector2i::new(100, 0));
     window.set_vertical_sync_enabled(true);
     //window.set_size(&Vector2u::new(400, 300));
+    window
+}
+
+fn load_font() -> Font {
+    let default_font = "C:\\Users\\super\\.cargo\\registry\\src\\github.com-88ac128001ac3a9a\\sfml-0.11.2\\examples\\resources\\sansation.ttf";
+    //let font_filename = "/Users/aovchinn/Downloads/SourceCodePro_FontsOnly-1.017/TTF/SourceCodePro-Regular.ttf";
+    let font_filename = env::var("POLYGON_FONT").unwrap_or(default_font.to_string());
+    Font::new_from_file(&font_filename).unwrap()
+}
+
+pub fn run(workspace: &str, seed: u64) {
+    let ws_dir = dir_of_workspace(workspace).unwrap();
+    let mut window = open_window();
 
     let ws = window.get_size();
     println!("Window size: {:?}", ws);
@@ -124,7 +132,7 @@ pub fn run(workspace: &str) {
     let plot_view = View::new(Rect::new(0.0, 0.0, 200.0, 200.0),
                               Rect::new(0.0, 1.0, 2.0, 0.0));
 
-    let mut pg = Polygon::new(ws_dir.clone());
+    let mut pg = Polygon::new(ws_dir.clone(), seed);
     let state_dim = pg.current_world().state.len() as i32;
     let mut v_fn = Vec::new();
     let mut ac_fn0 = Vec::new();
@@ -139,14 +147,13 @@ pub fn run(workspace: &str) {
 
     let loop_cycles = 100;
     let mut all_cycles = 0;
-    let font_filename = "C:\\Users\\super\\.cargo\\registry\\src\\github.com-88ac128001ac3a9a\\sfml-0.11.2\\examples\\resources\\sansation.ttf";
-    //let font_filename = "/Users/aovchinn/Downloads/SourceCodePro_FontsOnly-1.017/TTF/SourceCodePro-Regular.ttf";
-    let font = Font::new_from_file(font_filename).unwrap();
+    let font = load_font();
 
     let mut pause = false;
 
     let mut screen = 0;
 
+    let mut metrics_log = MetricsLog::open(&ws_dir, Format::Csv).unwrap();
     let mut avg_rewards = Vec::with_capacity(10000000);
     let mut ar = 0.0;
     loop {
@@ -158,6 +165,9 @@ pub fn run(workspace: &str) {
             if all_cycles % 10000 == 0 {
                 avg_rewards.push(Pt::new(all_cycles as f64, ar / 10000.0));
                 ar = 0.0;
+                if let Err(e) = metrics_log.write(&pg.metrics()) {
+                    println!("{}", e);
+                }
             }
         }
         //if all_cycles % (100 * loop_cycles) == 0 {
@@ -228,27 +238,45 @@ pub fn run(workspace: &str) {
             let ps = world.get_polyshape(view, &pss0);
             window.draw(&ps);
 
-            for i in 1..pg.get_worlds_size() {
+            // Cars on tracks of their own would be drawn on the wrong one
+            let others = if track_per_world(&pg.config.track) { 1 } else { pg.get_worlds_size() };
+            for i in 1..others {
                 let world = pg.get_world(i);
                 let car = &world.car;
                 let ps_car = car.get_polyshape(view, &pss0);
                 window.draw(&ps_car);
             }
 
-            let sigma = pg.learner.state.sigma.borrow();
+            let sigma = pg.learner.sigma();
 
             let car = &pg.get_world(0).car;
             let ps_car = car.get_polyshape(view, &pss1);
             window.draw(&ps_car);
 
-            let text = format!("Cycles: {}\nSpeed:  {}\nWheels: {}\nAct[0]: {}\n\
+            let mut text = format!("Cycles: {}\nSpeed:  {}\nWheels: {}\nAct[0]: {}\n\
                                 Act[1]: {}\nReward: {}\nX: {}\nY: {}\n\
                                 Offset: {}\nSigma: {}",
                         all_cycles, car.speed, car.wheels_angle,
                         world.last_action[0], world.last_action[1],
                         pg.last_reward, car.center.x, car.center.y,
                         10.0 * world.way.offset(&world.old_way_point, &world.way_point),
-                        sigma.deref());
+                        sigma);
+            let laps = &world.laps;
+            text.push_str(&format!("\nLaps: {}\nCheckpoint: {} / {}",
+                                   laps.laps, laps.next_checkpoint, laps.checkpoints()));
+            if let (Some(last), Some(best)) = (laps.last_lap, laps.best_lap) {
+                text.push_str(&format!("\nLap time: {} (best {})", last, best));
+            }
+            if laps.wrong_way_steps > 0 {
+                text.push_str("\nWrong way");
+            }
+            if let Some((ret, len)) = pg.episodes.mean(100) {
+                text.push_str(&format!("\nEpisodes: {}\nAvg return: {}\nAvg length: {}",
+                                       pg.episodes.count, ret, len));
+            }
+            for (name, v) in world.reward_fn.term_names().iter().zip(world.reward_terms.iter()) {
+                text.push_str(&format!("\n{}: {}", name, v));
+            }
 
             let mut txt = Text::new().unwrap();
             txt.set_font(&font);
@@ -296,6 +324,102 @@ pub fn run(workspace: &str) {
     }
 }
 
+/// Plays a recorded episode: Space pauses, `,` and `.` step back
+/// and forth while paused, R starts over, A and Z zoom. With
+/// `resimulate` the car is driven by the recorded actions
+/// instead of being put to the recorded poses.
+pub fn replay(filename: &str, resimulate: bool) {
+    let recording = Recording::load(path::Path::new(filename)).unwrap();
+    let mut world = recording.world().unwrap();
+    let frames = if resimulate {
+        let frames = recording.resimulate().unwrap();
+        if let Some(i) = recording.divergence(&frames, 1e-6) {
+            println!("Simulation departs from the recording at step {}", i);
+        }
+        frames
+    } else {
+        recording.frames.clone()
+    };
+    if frames.is_empty() {
+        println!("Nothing recorded");
+        return;
+    }
+
+    let mut window = open_window();
+    let ws = window.get_size();
+    let mut view = View::new(Rect::new(0.0, 0.0, ws.y as f32, ws.y as f32),
+                         Rect::new(-120.0, 120.0, 120.0, -120.0));
+    let font = load_font();
+    let pss0 = PolyshapeStyle::new();
+    let mut pss1 = PolyshapeStyle::new();
+    pss1.set_outline_color(Color::red()).set_fill_color(Color::red());
+
+    let mut pause = false;
+    let mut step = 0;
+    loop {
+        for event in window.events() {
+            match event {
+                event::Closed => return,
+                event::KeyPressed { code: Key::Escape, .. } => return,
+                event::KeyPressed { code: Key::A, ..} => {
+                    view.scale.x *= 2.0;
+                    view.scale.y *= 2.0;
+                },
+                event::KeyPressed { code: Key::Z, ..} => {
+                    view.scale.x /= 2.0;
+                    view.scale.y /= 2.0;
+                },
+                event::KeyPressed { code: Key::Space, ..} => {
+                    pause = !pause;
+                },
+                event::KeyPressed { code: Key::Comma, ..} => {
+                    if step > 0 {
+                        step -= 1;
+                    }
+                },
+                event::KeyPressed { code: Key::Period, ..} => {
+                    if step < frames.len() - 1 {
+                        step += 1;
+                    }
+                },
+                event::KeyPressed { code: Key::R, ..} => {
+                    step = 0;
+                },
+                _ => {}
+            }
+        }
+        let frame = &frames[step];
+        world.car.set_pos(frame.center(), frame.course());
+
+        window.clear(&Color::white());
+        window.draw(&world.get_polyshape(view, &pss0));
+        window.draw(&world.car.get_polyshape(view, &pss1));
+        let mut text = format!("Step: {} / {}\nSpeed:  {}\nWheels: {}\nAct[0]: {}\n\
+                                Act[1]: {}\nReward: {}\nX: {}\nY: {}",
+                               step + 1, frames.len(), frame.speed, frame.wheels_angle,
+                               frame.action[0], frame.action[1], frame.reward,
+                               frame.center[0], frame.center[1]);
+        if step == frames.len() - 1 {
+            if let Some(ref done) = recording.done {
+                text.push_str(&format!("\nDone: {}", done));
+            }
+        }
+        let mut txt = Text::new().unwrap();
+        txt.set_font(&font);
+        txt.set_character_size(24);
+        txt.set_string(&text);
+        txt.set_position2f(1200.0, 30.0);
+        txt.set_color(&Color::black());
+        window.draw(&txt);
+        window.display();
+
+        if !pause && step < frames.len() - 1 {
+            step += 1;
+        }
+        sleep(Duration::from_millis(20));
+    }
+}
+
 fn sin(x: f64) -> f64{
     x.sin().exp().sin()
 }
\ No newline at end of file